@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+use crate::input::Input;
+use crate::nfa::{Assertion, Inst, Program};
+
+/// The cache is dropped and rebuilt when it grows beyond this many states.
+const MAX_CACHED_STATES: usize = 10_000;
+
+/// A search that fills the cache more often than this gives up, as hardly
+/// any of the states it builds are reused.
+const MAX_RESETS_PER_SEARCH: usize = 3;
+
+type StateId = usize;
+
+const UNKNOWN: StateId = usize::MAX;
+
+/// What the DFA consumes in one step: a char, a byte in byte mode
+/// programs, or a byte that is not valid UTF-8 otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Symbol {
+    Char(char),
+    Byte(u8),
+    Invalid,
+}
+
+impl Symbol {
+    /// Index into the transition table for ASCII, which looks the same in
+    /// both modes.
+    fn ascii_index(self) -> Option<usize> {
+        match self {
+            Symbol::Char(c) if c.is_ascii() => Some(c as usize),
+            Symbol::Byte(byte) if byte.is_ascii() => Some(byte as usize),
+            _ => None,
+        }
+    }
+
+    fn is_accepted_by(self, inst: &Inst) -> bool {
+        match self {
+            Symbol::Char(c) => inst.accepts(c),
+            Symbol::Byte(byte) => inst.accepts_byte(byte),
+            Symbol::Invalid => false,
+        }
+    }
+}
+
+/// A DFA state: the set of NFA instructions the threads are waiting on.
+#[derive(Debug, Clone)]
+struct State {
+    pcs: Vec<usize>,
+    is_match: bool,
+    ascii: Box<[StateId; 128]>,
+    other: HashMap<Symbol, StateId>,
+}
+
+/// A DFA built lazily from a `Program` while searching, answering only
+/// whether a text matches at all.
+///
+/// Each DFA state is the set of NFA threads alive at a position. States and
+/// their transitions are computed the first time they are needed and cached
+/// for all later searches, so matching a line is a single pass with one
+/// table lookup per char in the common case.
+///
+/// Word boundary assertions look at the char after the current position,
+/// which does not fit this model; programs using them are not supported.
+///
+/// The cache is bounded, also within one search. Searches that keep
+/// producing new states return `None`, and the caller is expected to use
+/// another engine.
+#[derive(Debug, Clone)]
+pub struct LazyDfa {
+    program: Program,
+    states: Vec<State>,
+    state_ids: HashMap<Vec<usize>, StateId>,
+    start_state: StateId,
+    matches_at_end: HashMap<StateId, bool>,
+}
+
+impl LazyDfa {
+    pub fn new(program: &Program) -> Option<LazyDfa> {
+        let has_word_assertion = program.insts.iter().any(|inst| {
+            matches!(inst, Inst::Assert(assertion) if !matches!(assertion, Assertion::Start | Assertion::End))
+        });
+        if has_word_assertion {
+            return None;
+        }
+
+        let mut dfa = LazyDfa {
+            program: program.clone(),
+            states: vec![],
+            state_ids: HashMap::new(),
+            start_state: UNKNOWN,
+            matches_at_end: HashMap::new(),
+        };
+        dfa.reset();
+        Some(dfa)
+    }
+
+    pub fn is_match(&mut self, input: &Input) -> Option<bool> {
+        if self.states.len() > MAX_CACHED_STATES {
+            self.reset();
+        }
+
+        let mut state = self.start_state;
+        let mut pos = 0;
+        let mut resets = 0;
+
+        while pos < input.len() {
+            if self.states[state].is_match {
+                return Some(true);
+            }
+            if self.states[state].pcs.is_empty() {
+                return Some(false);
+            }
+            let (symbol, len) = if self.program.byte_mode {
+                (Symbol::Byte(input.bytes()[pos]), 1)
+            } else {
+                input.char_at(pos).map_or((Symbol::Invalid, 1), |(c, len)| (Symbol::Char(c), len))
+            };
+            state = self.next_state(state, symbol);
+            pos += len;
+
+            if self.states.len() > MAX_CACHED_STATES {
+                resets += 1;
+                if resets > MAX_RESETS_PER_SEARCH {
+                    return None;
+                }
+                let pcs = self.states[state].pcs.clone();
+                self.reset();
+                state = self.intern(pcs);
+            }
+        }
+
+        Some(self.states[state].is_match || self.match_at_end(state, input.len() == 0))
+    }
+
+    fn reset(&mut self) {
+        self.states.clear();
+        self.state_ids.clear();
+        self.matches_at_end.clear();
+        let pcs = self.closure(&[0], true, false);
+        self.start_state = self.intern(pcs);
+    }
+
+    fn next_state(&mut self, state: StateId, symbol: Symbol) -> StateId {
+        let cached = match symbol.ascii_index() {
+            Some(idx) => self.states[state].ascii[idx],
+            None => self.states[state].other.get(&symbol).copied().unwrap_or(UNKNOWN),
+        };
+        if cached != UNKNOWN {
+            return cached;
+        }
+
+        let mut roots: Vec<usize> = self.states[state]
+            .pcs
+            .iter()
+            .filter(|pc| symbol.is_accepted_by(&self.program.insts[**pc]))
+            .map(|pc| pc + 1)
+            .collect();
+        if !self.program.anchored {
+            // A new match may start at every position
+            roots.push(0);
+        }
+        let pcs = self.closure(&roots, false, false);
+        let next = self.intern(pcs);
+
+        match symbol.ascii_index() {
+            Some(idx) => self.states[state].ascii[idx] = next,
+            None => {
+                self.states[state].other.insert(symbol, next);
+            }
+        }
+        next
+    }
+
+    /// Whether the threads of `state` reach `Match` when the input ends
+    /// here, which can only happen through `$`.
+    fn match_at_end(&mut self, state: StateId, at_start: bool) -> bool {
+        if at_start {
+            // Only the empty input ends at the start; not worth caching
+            let pcs = self.closure(&self.states[state].pcs, true, true);
+            return pcs.iter().any(|pc| self.program.insts[*pc] == Inst::Match);
+        }
+        if let Some(matches) = self.matches_at_end.get(&state) {
+            return *matches;
+        }
+        let pcs = self.closure(&self.states[state].pcs, false, true);
+        let matches = pcs.iter().any(|pc| self.program.insts[*pc] == Inst::Match);
+        self.matches_at_end.insert(state, matches);
+        matches
+    }
+
+    /// Collects the instructions reachable from `roots` without consuming
+    /// input. `$` is only passed if `at_end` is set; otherwise the thread
+    /// stays on it.
+    fn closure(&self, roots: &[usize], at_start: bool, at_end: bool) -> Vec<usize> {
+        let mut visited = vec![false; self.program.insts.len()];
+        let mut pcs = vec![];
+        let mut stack = roots.to_vec();
+        while let Some(pc) = stack.pop() {
+            if visited[pc] {
+                continue;
+            }
+            visited[pc] = true;
+            match &self.program.insts[pc] {
+                Inst::Jmp(target) => stack.push(*target),
+                Inst::Split(first, second) => {
+                    stack.push(*second);
+                    stack.push(*first);
+                }
+                Inst::Save(_) => stack.push(pc + 1),
+                Inst::Assert(Assertion::Start) if at_start => stack.push(pc + 1),
+                Inst::Assert(Assertion::Start) => {}
+                Inst::Assert(Assertion::End) if at_end => stack.push(pc + 1),
+                _ => pcs.push(pc),
+            }
+        }
+        pcs
+    }
+
+    fn intern(&mut self, mut pcs: Vec<usize>) -> StateId {
+        pcs.sort_unstable();
+        pcs.dedup();
+        if let Some(id) = self.state_ids.get(&pcs) {
+            return *id;
+        }
+
+        let is_match = pcs.iter().any(|pc| self.program.insts[*pc] == Inst::Match);
+        let id = self.states.len();
+        self.states.push(State {
+            pcs: pcs.clone(),
+            is_match,
+            ascii: Box::new([UNKNOWN; 128]),
+            other: HashMap::new(),
+        });
+        self.state_ids.insert(pcs, id);
+        id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pikevm::PikeVm;
+    use crate::regex_parser::RegexParser;
+
+    fn compile(pattern: &str) -> Program {
+        let matcher = RegexParser::new(pattern).parse().unwrap();
+        Program::compile(&matcher).unwrap()
+    }
+
+    #[test]
+    fn test_agrees_with_pikevm() {
+        let patterns = [
+            "a.*b.*c",
+            "(a|ab)(c|bcd)",
+            r"\d+\.\d*",
+            "^ab",
+            "b$",
+            "^$",
+            "^(a|b)*$",
+            "(?i)grüße",
+            "[^[:alpha:]]",
+            "x*",
+            "",
+            r"(?-u)\xC3[\x80-\x9F]",
+            "(?-u)^.b",
+        ];
+        let texts = ["", "abc", "xaxxbxxcx", "abcd", "12.5", "cab", "abab", "GRÜSSE GRÜßE", "äö", "öb"];
+
+        for pattern in patterns {
+            let program = compile(pattern);
+            let mut dfa = LazyDfa::new(&program).unwrap();
+            for text in texts {
+                let input = Input::new(text.as_bytes());
+                assert_eq!(
+                    dfa.is_match(&input),
+                    Some(PikeVm::new(&program).find_at(&input, 0).is_some()),
+                    "pattern {pattern:?} on {text:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_word_boundaries_are_not_supported() {
+        assert!(LazyDfa::new(&compile(r"\bfoo")).is_none());
+        assert!(LazyDfa::new(&compile("^foo$")).is_some());
+    }
+
+    #[test]
+    fn test_invalid_utf8_matches_nothing() {
+        let mut dfa = LazyDfa::new(&compile("a.b")).unwrap();
+        assert_eq!(dfa.is_match(&Input::new(b"a\xffb")), Some(false));
+        assert_eq!(dfa.is_match(&Input::new(b"\xffaxb")), Some(true));
+    }
+
+    #[test]
+    fn test_cache_is_bounded_within_a_search() {
+        // Random a/b text reaches a new state for almost every char
+        let mut seed = 1u32;
+        let text: Vec<u8> = (0..200_000)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                if (seed >> 16) & 1 == 0 { b'a' } else { b'b' }
+            })
+            .collect();
+
+        let mut dfa = LazyDfa::new(&compile("a(a|b){14}c")).unwrap();
+        assert_eq!(dfa.is_match(&Input::new(&text)), None);
+        assert!(dfa.states.len() <= MAX_CACHED_STATES + 1);
+
+        // A few resets are fine
+        let mut dfa = LazyDfa::new(&compile("a(a|b){14}c")).unwrap();
+        let text = [&text[..20_000], b"c"].concat();
+        assert_eq!(dfa.is_match(&Input::new(&text)), Some(true));
+    }
+}
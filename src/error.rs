@@ -0,0 +1,105 @@
+use std::fmt;
+use thiserror::Error;
+
+/// A range of character positions in a pattern, `start` inclusive and `end`
+/// exclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    pub fn at(index: usize) -> Self {
+        Self::new(index, index + 1)
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+/// A search that was given up, so it is unknown whether the text matches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum MatchError {
+    #[error("pattern too complex: the match needs too much stack space")]
+    TooDeep,
+}
+
+/// Everything that can go wrong while parsing a pattern.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum RegexError {
+    #[error("'{quantifier}' has nothing to repeat")]
+    DanglingQuantifier { quantifier: char, span: Span },
+
+    #[error("unmatched '('")]
+    UnclosedParen { span: Span },
+
+    #[error("empty alternative")]
+    EmptyAlternative { span: Span },
+
+    #[error("pattern ends with a trailing backslash")]
+    TrailingBackslash { span: Span },
+
+    #[error("unknown escape sequence '\\{escape}'")]
+    BadEscape { escape: char, span: Span },
+
+    #[error("backreference \\{group_idx} refers to a group that is not defined before it")]
+    BadBackreference { group_idx: usize, span: Span },
+
+    #[error("invalid repetition count")]
+    BadRepetition { span: Span },
+
+    #[error("invalid repetition range {{{min},{max}}}")]
+    BadRange { min: usize, max: usize, span: Span },
+
+    #[error("unterminated repetition '{{'")]
+    UnclosedRepetition { span: Span },
+
+    #[error("unterminated character class '['")]
+    UnclosedBracket { span: Span },
+
+    #[error("invalid character range")]
+    BadCharRange { span: Span },
+
+    #[error("unknown character class '[:{name}:]'")]
+    BadCharClass { name: String, span: Span },
+
+    #[error("invalid hexadecimal escape")]
+    BadHexEscape { span: Span },
+
+    #[error("unknown flag '{flag}'")]
+    BadFlag { flag: char, span: Span },
+
+    #[error("'{ch}' is not a single byte; use '\\xHH' in a bracket expression in byte mode")]
+    NonByteClassMember { ch: char, span: Span },
+}
+
+impl RegexError {
+    pub fn span(&self) -> Span {
+        use RegexError::*;
+        match self {
+            DanglingQuantifier { span, .. }
+            | UnclosedParen { span }
+            | EmptyAlternative { span }
+            | TrailingBackslash { span }
+            | BadEscape { span, .. }
+            | BadBackreference { span, .. }
+            | BadRepetition { span }
+            | BadRange { span, .. }
+            | UnclosedRepetition { span }
+            | UnclosedBracket { span }
+            | BadCharRange { span }
+            | BadCharClass { span, .. }
+            | BadHexEscape { span }
+            | BadFlag { span, .. }
+            | NonByteClassMember { span, .. } => *span,
+        }
+    }
+}
@@ -0,0 +1,90 @@
+/// The text being searched, as bytes with a cursor-style API.
+///
+/// All positions are byte offsets. Chars are decoded in place, so looking at
+/// the char at a position is O(1) no matter how long the text is.
+#[derive(Debug, Clone, Copy)]
+pub struct Input<'t> {
+    bytes: &'t [u8],
+}
+
+impl<'t> Input<'t> {
+    pub fn new(bytes: &'t [u8]) -> Self {
+        Self { bytes }
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn bytes(&self) -> &'t [u8] {
+        self.bytes
+    }
+
+    pub fn byte_at(&self, pos: usize) -> Option<u8> {
+        self.bytes.get(pos).copied()
+    }
+
+    /// The char starting at `pos` and its length in bytes. Returns `None` at
+    /// the end of the input and for bytes that are not valid UTF-8.
+    pub fn char_at(&self, pos: usize) -> Option<(char, usize)> {
+        decode_utf8(self.bytes.get(pos..)?)
+    }
+
+    /// The char ending right before `pos`.
+    pub fn char_before(&self, pos: usize) -> Option<char> {
+        let start = (pos.saturating_sub(4)..pos)
+            .rev()
+            .find(|idx| !is_continuation_byte(self.bytes[*idx]))?;
+        self.char_at(start).map(|(ch, _)| ch)
+    }
+
+    /// Length of the char at `pos`, or 1 for invalid bytes and at the end so
+    /// that callers can always step forward.
+    pub fn step_len(&self, pos: usize) -> usize {
+        self.char_at(pos).map_or(1, |(_, len)| len)
+    }
+}
+
+fn is_continuation_byte(byte: u8) -> bool {
+    byte & 0xC0 == 0x80
+}
+
+fn decode_utf8(bytes: &[u8]) -> Option<(char, usize)> {
+    let first = *bytes.first()?;
+    if first < 0x80 {
+        return Some((first as char, 1));
+    }
+    let len = match first {
+        0xC2..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF4 => 4,
+        _ => return None,
+    };
+    let ch = std::str::from_utf8(bytes.get(..len)?).ok()?.chars().next()?;
+    Some((ch, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decodes_chars_in_place() {
+        let input = Input::new("aö😀".as_bytes());
+        assert_eq!(input.char_at(0), Some(('a', 1)));
+        assert_eq!(input.char_at(1), Some(('ö', 2)));
+        assert_eq!(input.char_at(3), Some(('😀', 4)));
+        assert_eq!(input.char_at(7), None);
+        assert_eq!(input.char_before(7), Some('😀'));
+        assert_eq!(input.char_before(3), Some('ö'));
+        assert_eq!(input.char_before(0), None);
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        let input = Input::new(b"a\xFFb");
+        assert_eq!(input.char_at(1), None);
+        assert_eq!(input.step_len(1), 1);
+        assert_eq!(input.char_at(2), Some(('b', 1)));
+    }
+}
@@ -1,10 +1,28 @@
-use crate::regex_parser::RegexParser;
 use clap::Parser;
-use std::{io, process};
-use crate::matcher::Match;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::process;
 
+mod dfa;
+mod error;
+mod input;
+mod literal;
 mod matcher;
+mod nfa;
+mod pikevm;
+mod regex;
 mod regex_parser;
+mod searcher;
+
+pub use crate::error::{MatchError, RegexError, Span};
+pub use crate::matcher::Match;
+pub use crate::regex::{PatternError, Regex};
+pub use crate::searcher::{SearchError, Searcher};
+use crate::searcher::STDIN_NAME;
+
+/// File name that stands for stdin.
+const STDIN_ARG: &str = "-";
 
 #[derive(Debug, Clone)]
 pub enum ColorMode {
@@ -13,6 +31,17 @@ pub enum ColorMode {
     Never,
 }
 
+/// How files that look binary (contain NUL bytes) are handled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryFiles {
+    /// Search them, but only report "Binary file X matches"
+    Binary,
+    /// Search and print them like text
+    Text,
+    /// Skip them as if they did not match
+    WithoutMatch,
+}
+
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
 pub struct Config {
@@ -32,6 +61,144 @@ pub struct Config {
 
     #[arg(long = "color", default_value = "never", value_parser = get_color_mode)]
     pub color: ColorMode,
+
+    #[arg(short, long, overrides_with_all = ["no_ignore_case", "smart_case"])]
+    pub ignore_case: bool,
+
+    #[arg(long, overrides_with_all = ["ignore_case", "smart_case"])]
+    pub no_ignore_case: bool,
+
+    /// Ignore case unless the pattern contains an uppercase letter
+    #[arg(short = 'S', long, overrides_with_all = ["ignore_case", "no_ignore_case"])]
+    pub smart_case: bool,
+
+    /// Suppress error messages about nonexistent or unreadable files
+    #[arg(short = 's', long)]
+    pub no_messages: bool,
+
+    /// Select the lines that do not match
+    #[arg(short = 'v', long)]
+    pub invert_match: bool,
+
+    /// Only print the names of files with selected lines
+    #[arg(short = 'l', long, overrides_with = "files_without_match")]
+    pub files_with_matches: bool,
+
+    /// Only print the names of files without selected lines
+    #[arg(short = 'L', long, overrides_with = "files_with_matches")]
+    pub files_without_match: bool,
+
+    /// Only print the number of selected lines per file
+    #[arg(short = 'c', long)]
+    pub count: bool,
+
+    /// Only print the number of matches per file, counting every match on
+    /// a line
+    #[arg(long)]
+    pub count_matches: bool,
+
+    /// Print NUM lines of context after each selected line
+    #[arg(short = 'A', long, value_name = "NUM")]
+    pub after_context: Option<usize>,
+
+    /// Print NUM lines of context before each selected line
+    #[arg(short = 'B', long, value_name = "NUM")]
+    pub before_context: Option<usize>,
+
+    /// Print NUM lines of context around each selected line
+    #[arg(short = 'C', long, value_name = "NUM")]
+    pub context: Option<usize>,
+
+    /// Print SEP between groups of lines that are not adjacent
+    #[arg(long, value_name = "SEP", default_value = "--")]
+    pub group_separator: String,
+
+    /// Do not print a separator between groups of lines
+    #[arg(long)]
+    pub no_group_separator: bool,
+
+    /// Prefix each output line with its 1-based line number
+    #[arg(short = 'n', long)]
+    pub line_number: bool,
+
+    /// Prefix each output line with its 0-based byte offset in the input,
+    /// or that of the match with -o
+    #[arg(short = 'b', long)]
+    pub byte_offset: bool,
+
+    /// Prefix each output line with the 1-based column of the first match,
+    /// or of each match with -o
+    #[arg(long)]
+    pub column: bool,
+
+    #[arg(long = "binary-files", value_name = "TYPE", default_value = "binary", value_parser = get_binary_files)]
+    pub binary_files: BinaryFiles,
+
+    /// Process binary files as if they were text, same as --binary-files=text
+    #[arg(short = 'a', long)]
+    pub text: bool,
+
+    /// Skip binary files, same as --binary-files=without-match
+    #[arg(short = 'I')]
+    pub skip_binary_files: bool,
+
+    /// Flush output after every line, even when not writing to a terminal
+    #[arg(long)]
+    pub line_buffered: bool,
+}
+
+impl Config {
+    /// Whether the pattern should be matched regardless of case, taking
+    /// smart case into account.
+    pub fn case_insensitive(&self) -> bool {
+        self.ignore_case || (self.smart_case && !has_uppercase_literal(&self.pattern))
+    }
+
+    /// Lines of context to print before selected lines. `-B` takes
+    /// precedence over `-C`.
+    pub fn lines_before(&self) -> usize {
+        self.before_context.or(self.context).unwrap_or(0)
+    }
+
+    /// Lines of context to print after selected lines. `-A` takes
+    /// precedence over `-C`.
+    pub fn lines_after(&self) -> usize {
+        self.after_context.or(self.context).unwrap_or(0)
+    }
+
+    /// How to treat binary files, taking the `-a` and `-I` shortcuts into
+    /// account.
+    pub fn binary_files_mode(&self) -> BinaryFiles {
+        if self.text {
+            BinaryFiles::Text
+        } else if self.skip_binary_files {
+            BinaryFiles::WithoutMatch
+        } else {
+            self.binary_files
+        }
+    }
+}
+
+/// Checks for uppercase letters in a pattern, skipping escape sequences like
+/// `\D` or `\u{1F600}` which do not match uppercase text.
+fn has_uppercase_literal(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => match chars.next() {
+                Some('x') => {
+                    chars.nth(1);
+                }
+                Some('u') => {
+                    chars.find(|c| *c == '}');
+                }
+                _ => {}
+            },
+            _ if ch.is_uppercase() => return true,
+            _ => {}
+        }
+    }
+    false
 }
 
 fn get_color_mode(s: &str) -> Result<ColorMode, String> {
@@ -45,150 +212,261 @@ fn get_color_mode(s: &str) -> Result<ColorMode, String> {
     }
 }
 
-pub fn process_stdin(config: &Config) {
-    let mut input_lines = vec![];
+fn get_binary_files(s: &str) -> Result<BinaryFiles, String> {
+    match s {
+        "binary" => Ok(BinaryFiles::Binary),
+        "text" => Ok(BinaryFiles::Text),
+        "without-match" => Ok(BinaryFiles::WithoutMatch),
+        _ => Err(format!(
+            "'{s}' is not a valid value for --binary-files. Use 'binary', 'text', or 'without-match'."
+        )),
+    }
+}
+
+/// Searches all inputs named in `config` and exits with GNU grep's status:
+/// 0 if a line was selected (with `-L`: a file was listed), 1 if not, 2 if
+/// any error occurred. Without any file names, or for the name `-`, stdin is
+/// searched.
+pub fn process_inputs(config: &Config, regex: &Regex) {
+    let mut errors = ErrorReporter::new(io::stderr(), config.no_messages);
+    let out = BufWriter::new(io::stdout().lock());
+    process::exit(run(config, regex, out, &mut errors));
+}
 
-    loop {
-        let mut buffer = String::new();
-        let bytes_read = io::stdin().read_line(&mut buffer).unwrap();
-        if bytes_read == 0 {
-            break; // EOF reached
+/// Searches all inputs, writing the results to `out`, and returns the exit
+/// status.
+fn run(config: &Config, regex: &Regex, out: impl Write, errors: &mut ErrorReporter<impl Write>) -> i32 {
+    match search_inputs(config, regex, out, errors) {
+        Ok(_) if errors.had_error => 2,
+        Ok(found) => if found { 0 } else { 1 },
+        // Nobody is reading our output anymore
+        Err(SearchError::Write(err)) if err.kind() == io::ErrorKind::BrokenPipe => 2,
+        Err(err) => {
+            errors.report_message(&err.to_string());
+            2
         }
-        input_lines.push(buffer);
     }
+}
 
-    let mut found = false;
-
-    for input_line in input_lines {
-        let line = input_line.trim_end_matches(&['\n', '\r'][..]);
-        let matches = match_all(&line, &config.pattern);
+/// Writes `grep: path: reason` messages for inputs that cannot be searched
+/// to `out` and remembers that something went wrong.
+struct ErrorReporter<W: Write> {
+    out: W,
+    no_messages: bool,
+    had_error: bool,
+}
 
-        if matches.is_empty() {
-            continue;
-        }
+impl<W: Write> ErrorReporter<W> {
+    fn new(out: W, no_messages: bool) -> Self {
+        Self { out, no_messages, had_error: false }
+    }
 
-        found = true;
+    fn report(&mut self, path: &str, err: &io::Error) {
+        self.report_message(&format!("{path}: {}", io_error_reason(err)));
+    }
 
-        if !config.only_matches {
-            println!("{}", colorize_line(&line, &matches, &config.color));
-        } else {
-            for m in matches {
-                println!("{}", m.matched_text);
-            }
+    fn report_message(&mut self, message: &str) {
+        self.had_error = true;
+        if !self.no_messages {
+            // There is nowhere left to report a failure to write the message
+            let _ = writeln!(self.out, "grep: {message}");
         }
     }
+}
 
-    process::exit(if found { 0 } else { 1 });
+/// The description of an I/O error without the "(os error N)" suffix.
+fn io_error_reason(err: &io::Error) -> String {
+    let reason = err.to_string();
+    match reason.find(" (os error") {
+        Some(idx) => reason[..idx].to_string(),
+        None => reason,
+    }
 }
 
-pub fn process_files_or_dirs(config: &Config) {
-    let mut found = false;
-    let filenames: Vec<String> = if config.recursive {
-        config.files_or_dirs
-            .iter()
-            .flat_map(|file_or_dir| get_files(file_or_dir))
-            .collect()
+fn search_inputs(config: &Config,
+                 regex: &Regex,
+                 mut out: impl Write,
+                 errors: &mut ErrorReporter<impl Write>) -> Result<bool, SearchError> {
+    let filenames: Vec<String> = if config.files_or_dirs.is_empty() {
+        vec![STDIN_ARG.to_string()]
+    } else if config.recursive {
+        let mut files = vec![];
+        for file_or_dir in &config.files_or_dirs {
+            collect_files(Path::new(file_or_dir), &mut files, errors);
+        }
+        files
     } else {
         config.files_or_dirs.to_vec()
     };
 
-    let multiple_files = filenames.len() > 1;
+    let searcher = Searcher::new(config, regex, filenames.len() > 1);
+    let mut found = false;
 
     for filename in &filenames {
-        let file_content = std::fs::read_to_string(filename).unwrap();
-
-        for line in file_content.lines() {
-            let matches = match_all(line, &config.pattern);
-            if matches.is_empty() {
-                continue;
-            }
-            found = true;
-
-            if !config.only_matches {
-                if multiple_files {
-                    println!("{filename}:{}", colorize_line(line, &matches, &config.color));
-                } else {
-                    println!("{}", colorize_line(line, &matches, &config.color));
-                }
-            } else {
-                for m in matches {
-                    if multiple_files {
-                        println!("{filename}:{}", m.matched_text);
-                    } else {
-                        println!("{}", m.matched_text);
-                    }
-                }
-            }
+        let name = if filename == STDIN_ARG { STDIN_NAME } else { filename };
+        let result = if filename == STDIN_ARG {
+            searcher.search(io::stdin().lock(), name, &mut out)
+        } else {
+            File::open(filename)
+                .map_err(SearchError::Read)
+                .and_then(|file| searcher.search(BufReader::new(file), filename, &mut out))
+        };
+        match result {
+            Ok(matched) => found |= matched,
+            Err(SearchError::Read(err)) => errors.report(name, &err),
+            // Like an unreadable input, this only ends the search of this one
+            Err(err @ SearchError::Match { .. }) => errors.report_message(&format!("{name}: {err}")),
+            Err(err) => return Err(err),
         }
     }
 
-    if found {
-        process::exit(0);
-    } else {
-        process::exit(1);
+    out.flush()?;
+    Ok(found)
+}
+
+/// Adds `path` or, for a directory, all files below it to `files`. Entries
+/// that cannot be read are reported and skipped.
+fn collect_files(path: &Path, files: &mut Vec<String>, errors: &mut ErrorReporter<impl Write>) {
+    let name = path.to_string_lossy();
+    if name == STDIN_ARG {
+        files.push(name.to_string());
+        return;
+    }
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.is_dir() => collect_directory(path, files, errors),
+        Ok(_) => files.push(name.to_string()),
+        Err(err) => errors.report(&name, &err),
     }
 }
 
-fn colorize_line(line: &str, matches: &Vec<Match>, color_mode: &ColorMode) -> String {
-    let do_colorize = match color_mode {
-        ColorMode::Always => true,
-        ColorMode::Auto => atty::is(atty::Stream::Stdout),
-        ColorMode::Never => false,
+fn collect_directory(dir: &Path, files: &mut Vec<String>, errors: &mut ErrorReporter<impl Write>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => return errors.report(&dir.to_string_lossy(), &err),
     };
-
-    if do_colorize {
-        let mut colored_line = String::new();
-        let mut last_index = 0;
-
-        for m in matches {
-            let start = m.offset;
-            let end = start + m.matched_text.len();
-            colored_line.push_str(&line[last_index..start]);
-            colored_line.push_str("\x1b[1;31m"); // Start red color in bold
-            colored_line.push_str(&line[start..end]);
-            colored_line.push_str("\x1b[0m"); // Reset color
-            last_index = end;
+    for entry in entries {
+        match entry.and_then(|entry| Ok((entry.file_type()?, entry.path()))) {
+            // Symlinks are skipped below the given paths, like GNU grep -r
+            Ok((file_type, _)) if file_type.is_symlink() => {}
+            Ok((file_type, path)) if file_type.is_dir() => collect_directory(&path, files, errors),
+            Ok((_, path)) => files.push(path.to_string_lossy().to_string()),
+            Err(err) => errors.report(&dir.to_string_lossy(), &err),
         }
-        colored_line.push_str(&line[last_index..]);
-        colored_line
-    } else {
-        line.to_string()
     }
 }
 
-fn match_all(input_line: &str, pattern: &str) -> Vec<Match> {
-    RegexParser::new(pattern)
-        .parse()
-        .ok()
-        .map_or(vec![], |m| m.find_all_matches(input_line))
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
 
-fn get_files(file_or_dir: &str) -> Vec<String> {
-    let path = std::path::Path::new(file_or_dir);
-    if path.is_dir() {
-        match get_files_in_directory(path) {
-            Ok(files) => files
-                .into_iter()
-                .map(|p| p.to_string_lossy().to_string())
-                .collect(),
-            Err(_) => vec![],
-        }
-    } else if path.is_file() {
-        vec![file_or_dir.to_string()]
-    } else {
-        vec![]
+    /// Runs grep with `args` and returns the exit status, the output and the
+    /// error messages.
+    fn grep(args: &[&str]) -> (i32, String, String) {
+        let config = Config::parse_from(["grep"].iter().chain(args));
+        let regex = Regex::new_with_ignore_case(&config.pattern, config.case_insensitive()).unwrap();
+        let mut out = vec![];
+        let mut errors = ErrorReporter::new(vec![], config.no_messages);
+        let status = run(&config, &regex, &mut out, &mut errors);
+        (status, String::from_utf8(out).unwrap(), String::from_utf8(errors.out).unwrap())
     }
-}
 
-fn get_files_in_directory(dir: &std::path::Path) -> io::Result<Vec<std::path::PathBuf>> {
-    let mut files = Vec::new();
-    for entry in std::fs::read_dir(dir)? {
-        let entry = entry?;
-        if entry.file_type()?.is_dir() {
-            files.extend(get_files_in_directory(&entry.path())?);
-        } else {
-            files.push(entry.path());
-        }
+    /// An empty directory for the files of one test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("grep-test-{}-{name}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_io_error_reason() {
+        let err = io::Error::from_raw_os_error(2);
+        assert!(err.to_string().ends_with("(os error 2)"));
+        assert_eq!(io_error_reason(&err), "No such file or directory");
+        assert_eq!(io_error_reason(&io::Error::other("custom")), "custom");
+    }
+
+    #[test]
+    fn test_exit_status() {
+        let dir = temp_dir("status");
+        let file = dir.join("a.txt");
+        fs::write(&file, "one\ntwo\n").unwrap();
+        let file = file.to_str().unwrap();
+
+        assert_eq!(grep(&["-E", "two", file]), (0, "two\n".into(), "".into()));
+        assert_eq!(grep(&["-E", "three", file]), (1, "".into(), "".into()));
+    }
+
+    #[test]
+    fn test_context_groups_are_separated_across_files() {
+        let dir = temp_dir("context");
+        let (first, second) = (dir.join("d.txt"), dir.join("e.txt"));
+        fs::write(&first, "foo\ny\n").unwrap();
+        fs::write(&second, "foo\nz\n").unwrap();
+        let (first, second) = (first.to_str().unwrap(), second.to_str().unwrap());
+
+        let (status, out, _) = grep(&["-E", "-A1", "foo", first, second]);
+        assert_eq!(status, 0);
+        assert_eq!(out, format!("{first}:foo\n{first}-y\n--\n{second}:foo\n{second}-z\n"));
+
+        // Nothing is separated if the first file has no match
+        let (_, out, _) = grep(&["-E", "-B1", "z", first, second]);
+        assert_eq!(out, format!("{second}-foo\n{second}:z\n"));
+    }
+
+    #[test]
+    fn test_lines_too_complex_to_match_are_reported() {
+        let dir = temp_dir("too-deep");
+        let file = dir.join("a.txt");
+        fs::write(&file, format!("aabc\naa{}\n", "bc".repeat(200_000))).unwrap();
+        let file = file.to_str().unwrap();
+
+        let (status, out, messages) = grep(&["-E", r"(a)\1(bc)*$", file]);
+        assert_eq!((status, out), (2, "aabc\n".into()));
+        assert_eq!(messages, format!("grep: {file}: line 2: pattern too complex: the match needs too much stack space\n"));
+    }
+
+    #[test]
+    fn test_unreadable_inputs_are_reported() {
+        let dir = temp_dir("unreadable");
+        let file = dir.join("a.txt");
+        fs::write(&file, "match\n").unwrap();
+        let file = file.to_str().unwrap();
+        let missing = dir.join("missing.txt");
+        let missing = missing.to_str().unwrap();
+
+        // The search goes on after the missing file, but the status is 2
+        let (status, out, messages) = grep(&["-E", "match", missing, file]);
+        assert_eq!((status, out), (2, format!("{file}:match\n")));
+        assert_eq!(messages, format!("grep: {missing}: No such file or directory\n"));
+
+        let (status, out, messages) = grep(&["-E", "-s", "match", missing, file]);
+        assert_eq!((status, out, messages), (2, format!("{file}:match\n"), "".into()));
+
+        let (status, _, messages) = grep(&["-E", "-r", "match", missing]);
+        assert_eq!((status, messages), (2, format!("grep: {missing}: No such file or directory\n")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_recursive_search_skips_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let dir = temp_dir("symlinks");
+        fs::write(dir.join("a.txt"), "match\n").unwrap();
+        fs::create_dir_all(dir.join("d/sub")).unwrap();
+        fs::write(dir.join("d/sub/b.txt"), "match\n").unwrap();
+        symlink("../a.txt", dir.join("d/link_file")).unwrap();
+        symlink("sub", dir.join("d/link_dir")).unwrap();
+        symlink("nowhere", dir.join("d/dangling")).unwrap();
+
+        // Only sub/b.txt is searched, so no file names are printed
+        let d = dir.join("d");
+        assert_eq!(grep(&["-E", "-r", "match", d.to_str().unwrap()]), (0, "match\n".into(), "".into()));
+
+        // Symlinks named on the command line are followed
+        let link = d.join("link_dir");
+        assert_eq!(grep(&["-E", "-r", "match", link.to_str().unwrap()]), (0, "match\n".into(), "".into()));
     }
-    Ok(files)
 }
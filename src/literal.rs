@@ -0,0 +1,198 @@
+use crate::matcher::Matcher;
+
+/// Longest literal that is kept, so that large counted repetitions are not
+/// expanded in memory.
+const MAX_LITERAL_LEN: usize = 4096;
+
+/// Literal text the matches of a pattern are known to contain, used to rule
+/// out lines and start offsets before running a matching engine.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Literals {
+    /// Every match starts with these bytes.
+    pub prefix: Vec<u8>,
+    /// Every match contains these bytes.
+    pub required: Vec<u8>,
+}
+
+impl Literals {
+    pub fn extract(matcher: &Matcher) -> Self {
+        let info = Info::of(matcher);
+        Self {
+            prefix: info.prefix,
+            required: info.required,
+        }
+    }
+}
+
+/// What is known about the text matched by one node of the `Matcher` tree.
+#[derive(Debug, Default)]
+struct Info {
+    /// The node always matches exactly these bytes.
+    exact: Option<Vec<u8>>,
+    prefix: Vec<u8>,
+    suffix: Vec<u8>,
+    required: Vec<u8>,
+}
+
+impl Info {
+    fn exact(text: Vec<u8>) -> Self {
+        Self {
+            exact: Some(text.clone()),
+            prefix: text.clone(),
+            suffix: text.clone(),
+            required: text,
+        }
+    }
+
+    fn of(matcher: &Matcher) -> Self {
+        use Matcher::*;
+        match matcher {
+            SingleChar(c) => Self::exact(c.to_string().into_bytes()),
+            Byte(byte) => Self::exact(vec![*byte]),
+            StartMatcher | EndMatcher | WordBoundary(_) | NonWordBoundary(_) | WordStart(_) | WordEnd(_) =>
+                Self::exact(vec![]),
+            Sequence(matchers) => matchers
+                .iter()
+                .map(Self::of)
+                .fold(Self::exact(vec![]), Self::concat),
+            Multiple { matcher, min, max } => {
+                let inner = Self::of(matcher);
+                match &inner.exact {
+                    Some(text) if Some(*min) == *max && fits(text.len().checked_mul(*min)) =>
+                        Self::exact(text.repeat(*min)),
+                    _ if *min > 0 => Self { exact: None, ..inner },
+                    _ => Self::default(),
+                }
+            }
+            Group(matchers, _) | Alternation(matchers) => Self::alternatives(matchers),
+            SingleCharBranch(_) | Wildcard | GroupReference(..) | ByteBranch(_) | AnyByte => Self::default(),
+        }
+    }
+
+    fn concat(self, next: Self) -> Self {
+        if let (Some(first), Some(second)) = (&self.exact, &next.exact) {
+            if fits(first.len().checked_add(second.len())) {
+                return Self::exact([&first[..], second].concat());
+            }
+        }
+        let prefix = match &self.exact {
+            Some(text) => [text, &next.prefix[..]].concat(),
+            None => self.prefix,
+        };
+        let suffix = match &next.exact {
+            Some(text) => [&self.suffix[..], text].concat(),
+            None => next.suffix,
+        };
+        // A literal may also span the border between the two parts
+        let spanning = [self.suffix, next.prefix].concat();
+        let required = [self.required, next.required, spanning]
+            .into_iter()
+            .max_by_key(Vec::len)
+            .unwrap_or_default();
+        Self { exact: None, prefix, suffix, required }.truncated()
+    }
+
+    /// Shortens the literals to `MAX_LITERAL_LEN`, keeping the end of the
+    /// suffix.
+    fn truncated(mut self) -> Self {
+        self.prefix.truncate(MAX_LITERAL_LEN);
+        self.suffix.drain(..self.suffix.len().saturating_sub(MAX_LITERAL_LEN));
+        self.required.truncate(MAX_LITERAL_LEN);
+        self
+    }
+
+    fn alternatives(matchers: &[Matcher]) -> Self {
+        let mut infos = matchers.iter().map(Self::of);
+        let Some(first) = infos.next() else {
+            return Self::exact(vec![]);
+        };
+        infos.fold(first, |acc, info| {
+            if acc.exact.is_some() && acc.exact == info.exact {
+                return acc;
+            }
+            let prefix = common_prefix(&acc.prefix, &info.prefix);
+            let suffix = common_suffix(&acc.suffix, &info.suffix);
+            let required = if prefix.len() >= suffix.len() { prefix.clone() } else { suffix.clone() };
+            Self { exact: None, prefix, suffix, required }
+        })
+    }
+}
+
+fn fits(len: Option<usize>) -> bool {
+    len.is_some_and(|len| len <= MAX_LITERAL_LEN)
+}
+
+fn common_prefix(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let len = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    a[..len].to_vec()
+}
+
+fn common_suffix(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let len = a.iter().rev().zip(b.iter().rev()).take_while(|(x, y)| x == y).count();
+    a[a.len() - len..].to_vec()
+}
+
+/// Finds the first occurrence of `needle` in `haystack`. Scans for the first
+/// byte of the needle and only compares the rest at those candidates.
+pub fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    let Some((&first, rest)) = needle.split_first() else {
+        return Some(0);
+    };
+    let last_start = haystack.len().checked_sub(needle.len())?;
+    let mut pos = 0;
+
+    while pos <= last_start {
+        pos += haystack[pos..=last_start].iter().position(|b| *b == first)?;
+        if haystack[pos + 1..].starts_with(rest) {
+            return Some(pos);
+        }
+        pos += 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::regex_parser::RegexParser;
+
+    fn literals(pattern: &str) -> (String, String) {
+        let matcher = RegexParser::new(pattern).parse().unwrap();
+        let literals = Literals::extract(&matcher);
+        let text = |bytes| String::from_utf8(bytes).unwrap();
+        (text(literals.prefix), text(literals.required))
+    }
+
+    #[test]
+    fn test_extract_literals() {
+        assert_eq!(literals(r"ERROR \d+"), ("ERROR ".into(), "ERROR ".into()));
+        assert_eq!(literals(r"\s*user_id=(\d+)"), ("".into(), "user_id=".into()));
+        assert_eq!(literals("^ab(cd)e"), ("abcde".into(), "abcde".into()));
+        assert_eq!(literals("x{3}y+z"), ("xxxy".into(), "xxxy".into()));
+        assert_eq!(literals("(foobar|foobaz)!"), ("fooba".into(), "fooba".into()));
+        assert_eq!(literals(r"\d+ (cat|dog)s\b"), ("".into(), "s".into()));
+        assert_eq!(literals("a?b*"), ("".into(), "".into()));
+        assert_eq!(literals("(?i)abc"), ("".into(), "".into()));
+        assert_eq!(literals("(?-u)ü+"), ("ü".into(), "ü".into()));
+    }
+
+    #[test]
+    fn test_long_literals_are_truncated() {
+        let (prefix, required) = literals("x{32767}");
+        assert_eq!((prefix.len(), required.len()), (1, 1));
+        let (prefix, required) = literals("ab{4000}c{4000}d");
+        assert!(prefix.starts_with(&format!("a{}c", "b".repeat(4000))));
+        assert_eq!((prefix.len(), required.len()), (MAX_LITERAL_LEN, MAX_LITERAL_LEN));
+        assert_eq!(literals("(ab){2048}x"), ("ab".repeat(2048), "ab".repeat(2048)));
+    }
+
+    #[test]
+    fn test_find() {
+        assert_eq!(find(b"hello world", b"world"), Some(6));
+        assert_eq!(find(b"wow world", b"wor"), Some(4));
+        assert_eq!(find(b"abc", b""), Some(0));
+        assert_eq!(find(b"ab", b"abc"), None);
+        assert_eq!(find(b"aab", b"ab"), Some(1));
+        assert_eq!(find("grüße".as_bytes(), "ße".as_bytes()), Some(4));
+    }
+}
@@ -1,5 +1,5 @@
 use clap::Parser;
-use codecrafters_grep::{process_files_or_dirs, process_stdin, Config};
+use codecrafters_grep::{process_inputs, Config, Regex};
 use std::process;
 
 fn main() {
@@ -10,8 +10,13 @@ fn main() {
         process::exit(1);
     }
 
-    match config.files_or_dirs.len() {
-        0 => process_stdin(&config),
-        _ => process_files_or_dirs(&config),
-    }
+    let regex = match Regex::new_with_ignore_case(&config.pattern, config.case_insensitive()) {
+        Ok(regex) => regex,
+        Err(err) => {
+            eprintln!("grep: {err}");
+            process::exit(2);
+        }
+    };
+
+    process_inputs(&config, &regex);
 }
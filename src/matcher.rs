@@ -1,21 +1,73 @@
 use std::collections::HashMap;
+use crate::error::MatchError;
+use crate::input::Input;
+
+/// Spans of a match: slot 0 holds the whole match, slot `i` group `i`.
+pub type Captures = Vec<Option<(usize, usize)>>;
+
+/// Continuations run inside the matcher that calls them, so the recursion
+/// grows with the input for repetitions. A search is given up once a match
+/// attempt uses this many bytes of stack, which leaves room on a default
+/// 2 MiB thread.
+const MAX_STACK_USE: usize = 1 << 20;
+
+/// The captures of a match attempt so far, and where its recursion started.
+struct GroupResults {
+    spans: Captures,
+    stack_base: usize,
+    /// Set once `MAX_STACK_USE` is exceeded, giving up the attempt.
+    too_deep: bool,
+}
+
+impl GroupResults {
+    fn new(len: usize) -> Self {
+        Self { spans: vec![None; len], stack_base: 0, too_deep: false }
+    }
+
+    fn reset(&mut self, stack_base: usize) {
+        self.spans.fill(None);
+        self.stack_base = stack_base;
+        self.too_deep = false;
+    }
+}
+
+/// Approximate address of the top of the stack.
+fn stack_position() -> usize {
+    let marker = 0u8;
+    std::ptr::addr_of!(marker) as usize
+}
+
+/// Continuation invoked with the offset reached and the current group
+/// results. Returning `false` makes the caller backtrack.
+type Continuation<'a> = dyn FnMut(usize, &mut GroupResults) -> bool + 'a;
 
 #[derive(Clone, Debug, PartialEq)]
+#[allow(clippy::enum_variant_names)]
 pub enum Matcher {
     SingleChar(char),
     StartMatcher,
     EndMatcher,
-    SingleCharBranch(Vec<char>, bool),
+    // Word assertions hold the `unicode` flag; without it only ASCII chars
+    // are word chars
+    WordBoundary(bool),
+    NonWordBoundary(bool),
+    WordStart(bool),
+    WordEnd(bool),
+    SingleCharBranch(CharSet),
     Sequence(Vec<Matcher>),
     Multiple{
         matcher: Box<Matcher>,
         min: usize,
         max: Option<usize>,
-        follow: Option<Box<Matcher>>,
     },
     Wildcard,
     Group(Vec<Matcher>, usize),
-    GroupReference(usize),
+    Alternation(Vec<Matcher>),
+    GroupReference(usize, bool),
+    // Byte mode, see `(?-u)`: these consume one byte, valid UTF-8 or not
+    Byte(u8),
+    ByteBranch(CharSet),
+    AnyByte,
 }
 
 impl Matcher {
@@ -32,8 +84,39 @@ impl Matcher {
         Matcher::EndMatcher
     }
 
-    pub fn new_single_char_branch(chars: Vec<char>, negated: bool) -> Self {
-        Matcher::SingleCharBranch(chars, negated)
+    pub fn new_word_boundary(unicode: bool) -> Self {
+        Matcher::WordBoundary(unicode)
+    }
+
+    pub fn new_non_word_boundary(unicode: bool) -> Self {
+        Matcher::NonWordBoundary(unicode)
+    }
+
+    pub fn new_word_start(unicode: bool) -> Self {
+        Matcher::WordStart(unicode)
+    }
+
+    pub fn new_word_end(unicode: bool) -> Self {
+        Matcher::WordEnd(unicode)
+    }
+
+    pub fn new_single_char_branch(items: Vec<SetItem>, negated: bool, ignore_case: bool) -> Self {
+        Matcher::SingleCharBranch(CharSet { items, negated, ignore_case })
+    }
+
+    /// Matches `c` in any case. Chars that are not alphabetic stay a plain
+    /// `SingleChar`: no other char folds to the same char as them. Letters
+    /// always get a set, even without case variants of their own, as some
+    /// still have chars folding to them (`ẞ` to `ß`).
+    pub fn new_single_char_ignore_case(c: char) -> Self {
+        if !c.is_alphabetic() {
+            return Matcher::SingleChar(c);
+        }
+        Matcher::SingleCharBranch(CharSet {
+            items: vec![SetItem::Char(c)],
+            negated: false,
+            ignore_case: true,
+        })
     }
 
     pub fn new_sequence(matchers: Vec<Matcher>) -> Self {
@@ -42,17 +125,11 @@ impl Matcher {
 
         for matcher in matchers {
             if let Some(p) = &pending {
-                if p.is_mergeable_with(&matcher) {
-                    // Merge the two matchers
-                    let merged = p.merge_with(&matcher);
+                if let Some(merged) = p.merge_with(&matcher) {
                     pending = Some(merged);
                 } else {
                     // Push the pending matcher to new_matchers
-                    if !p.can_have_follow() {
-                        new_matchers.push(p.clone());
-                    } else {
-                        new_matchers.push(p.set_follow(&matcher));
-                    }
+                    new_matchers.push(p.clone());
                     pending = Some(matcher);
                 }
             } else {
@@ -67,27 +144,19 @@ impl Matcher {
         Matcher::Sequence(new_matchers)
     }
 
-    pub fn new_one_or_more(matcher: Box<Matcher>, follow: Option<&Matcher>) -> Self {
+    pub fn new_one_or_more(matcher: Box<Matcher>) -> Self {
         Matcher::Multiple{
             matcher,
             min: 1,
             max: None,
-            follow: match follow {
-                Some(f) => Some(Box::new(f.clone())),
-                None => None,
-            },
         }
     }
 
-    pub fn new_zero_or_more(matcher: Box<Matcher>, follow: Option<&Matcher>) -> Self {
+    pub fn new_zero_or_more(matcher: Box<Matcher>) -> Self {
         Matcher::Multiple{
             matcher,
             min: 0,
             max: None,
-            follow: match follow {
-                Some(f) => Some(Box::new(f.clone())),
-                None => None,
-            },
         }
     }
 
@@ -96,7 +165,6 @@ impl Matcher {
             matcher: Box::new(matcher.clone()),
             min: 0,
             max: Some(1),
-            follow: None,
         }
     }
 
@@ -108,37 +176,117 @@ impl Matcher {
         Matcher::Group(matchers, group_idx)
     }
 
-    pub fn new_group_reference(group_idx: usize) -> Self {
-        Matcher::GroupReference(group_idx)
+    /// Alternatives of a group that does not capture, like `(?:a|b)`.
+    pub fn new_alternation(matchers: Vec<Matcher>) -> Self {
+        Matcher::Alternation(matchers)
+    }
+
+    pub fn new_byte(byte: u8) -> Self {
+        Matcher::Byte(byte)
     }
 
-    pub fn matches(&self, text: &str) -> bool {
-        self.find_match(text).is_some()
+    /// Set of single bytes. Bytes from 0x80 up are only matched by chars and
+    /// ranges of `items` (as code points up to U+00FF), never by classes.
+    pub fn new_byte_branch(items: Vec<SetItem>, negated: bool, ignore_case: bool) -> Self {
+        Matcher::ByteBranch(CharSet { items, negated, ignore_case })
     }
 
+    pub fn new_any_byte() -> Self {
+        Matcher::AnyByte
+    }
+
+    pub fn new_group_reference(group_idx: usize, ignore_case: bool) -> Self {
+        Matcher::GroupReference(group_idx, ignore_case)
+    }
+
+    // Searching with the backtracker only, `Regex` picks the engine
+    #[cfg(test)]
     pub fn find_match(&self, text: &str) -> Option<Match> {
-        for offset in 0..text.chars().count() {
-            match self.check_match(text, offset, &HashMap::new()) {
-                Some(m) => return Some(m),
-                None => continue,
-            }
-        }
-        None
+        let captures = self.find_at(&Input::new(text.as_bytes()), 0).unwrap()?;
+        Some(Match::from_captures(text, &captures))
     }
 
+    #[cfg(test)]
     pub fn find_all_matches(&self, text: &str) -> Vec<Match> {
-        let mut ret = vec![];
-        let mut offset = 0;
-        while offset < text.chars().count() {
-            match self.check_match(text, offset, &HashMap::new()) {
-                Some(m) => {
-                    offset += m.matched_text.chars().count();
-                    ret.push(m);
-                }
-                None => offset += 1,
+        find_all_with(text, self.uses_bytes(), |input, start| self.find_at(input, start)).unwrap()
+    }
+
+    /// Finds the leftmost match starting at or after byte offset `start`.
+    ///
+    /// Fails if a match attempt needs more than `MAX_STACK_USE` bytes of
+    /// stack: whether it would have matched is unknown then.
+    pub fn find_at(&self, input: &Input, start: usize) -> Result<Option<Captures>, MatchError> {
+        let mut group_results = GroupResults::new(self.group_count() + 1);
+        let anchored = self.is_anchored_start();
+        let byte_mode = self.uses_bytes();
+        let mut offset = start;
+
+        loop {
+            if self.match_at(input, offset, &mut group_results)? {
+                return Ok(Some(group_results.spans));
+            }
+            if offset >= input.len() || anchored {
+                return Ok(None);
             }
+            offset += if byte_mode { 1 } else { input.step_len(offset) };
+        }
+    }
+
+    /// Like `find_at`, but only tries a match starting at exactly `offset`.
+    pub fn find_anchored_at(&self, input: &Input, offset: usize) -> Result<Option<Captures>, MatchError> {
+        let mut group_results = GroupResults::new(self.group_count() + 1);
+        Ok(self.match_at(input, offset, &mut group_results)?.then_some(group_results.spans))
+    }
+
+    /// Tries to match at exactly `offset`. On success `group_results` holds
+    /// the captures of the match.
+    fn match_at(&self, input: &Input, offset: usize, group_results: &mut GroupResults) -> Result<bool, MatchError> {
+        group_results.reset(stack_position());
+        let matched = self.check_match(input, offset, group_results, &mut |end, groups| {
+            groups.spans[0] = Some((offset, end));
+            // Shorter repetitions tried after giving up are not the leftmost-first match
+            !groups.too_deep
+        });
+        if group_results.too_deep {
+            return Err(MatchError::TooDeep);
+        }
+        Ok(matched)
+    }
+
+    /// Highest group index used in the pattern.
+    pub fn group_count(&self) -> usize {
+        use Matcher::*;
+        match self {
+            Sequence(matchers) | Alternation(matchers) =>
+                matchers.iter().map(Matcher::group_count).max().unwrap_or(0),
+            Group(matchers, group_idx) =>
+                matchers.iter().map(Matcher::group_count).fold(*group_idx, usize::max),
+            Multiple { matcher, .. } => matcher.group_count(),
+            _ => 0,
+        }
+    }
+
+    /// Whether every match has to start at the beginning of the text.
+    pub fn is_anchored_start(&self) -> bool {
+        use Matcher::*;
+        match self {
+            StartMatcher => true,
+            Sequence(matchers) => matchers.first().is_some_and(Matcher::is_anchored_start),
+            Group(matchers, _) | Alternation(matchers) => matchers.iter().all(Matcher::is_anchored_start),
+            _ => false,
+        }
+    }
+
+    /// Whether the pattern matches bytes anywhere, see `(?-u)`. Such a
+    /// pattern can match from inside a multi-byte char.
+    pub fn uses_bytes(&self) -> bool {
+        use Matcher::*;
+        match self {
+            Byte(_) | ByteBranch(_) | AnyByte => true,
+            Sequence(matchers) | Alternation(matchers) | Group(matchers, _) => matchers.iter().any(Matcher::uses_bytes),
+            Multiple { matcher, .. } => matcher.uses_bytes(),
+            _ => false,
         }
-        ret
     }
 
     fn is_mergeable_with(&self, other: &Matcher) -> bool {
@@ -154,323 +302,583 @@ impl Matcher {
         }
     }
 
-    fn merge_with(&self, other: &Matcher) -> Matcher {
+    /// Merges `other` into the repetition `self`, or returns `None` if they
+    /// cannot be merged or the merged counts would overflow.
+    fn merge_with(&self, other: &Matcher) -> Option<Matcher> {
         use Matcher::*;
 
         if !self.is_mergeable_with(other) {
-            panic!("Cannot merge non-mergeable matchers");
+            return None;
         }
 
         match (self, other) {
             (Multiple { matcher: m, min: min1, max: max1, .. },
              Multiple { min: min2, max: max2, .. }) => {
-                let new_min = min1 + min2;
+                let new_min = min1.checked_add(*min2)?;
                 let new_max = match (max1, max2) {
-                    (Some(v1), Some(v2)) => Some(v1 + v2),
+                    (Some(v1), Some(v2)) => Some(v1.checked_add(*v2)?),
                     _ => None,
                 };
-                Multiple {
+                Some(Multiple {
                     matcher: m.clone(),
                     min: new_min,
                     max: new_max,
-                    follow: None,
-                }
+                })
             }
             (Multiple { matcher: m, min, max, .. }, _) => {
-                let new_min = min + 1;
+                let new_min = min.checked_add(1)?;
                 let new_max = match max {
-                    Some(v) => Some(v + 1),
+                    Some(v) => Some(v.checked_add(1)?),
                     None => None,
                 };
-                Multiple {
+                Some(Multiple {
                     matcher: m.clone(),
                     min: new_min,
                     max: new_max,
-                    follow: None,
-                }
+                })
             }
-            _ => panic!("Cannot merge non-mergeable matchers"),
-        }
-    }
-
-    fn can_have_follow(&self) -> bool {
-        match self {
-            Matcher::Multiple {..} => true,
-            Matcher::Group(matchers,_) => {
-                let last_match = matchers.last().unwrap();
-                last_match.can_have_follow()
-            },
-            _ => false,
-        }
-    }
-
-    fn set_follow(&self, follow: &Matcher) -> Matcher  {
-        match self {
-            Matcher::Multiple { matcher, min, max,.. } => Matcher::Multiple {
-                matcher: matcher.clone(),
-                min: *min,
-                max: *max,
-                follow: Some(Box::new(follow.clone())),
-            },
-            Matcher::Group(matchers, group_idx) => {
-                let last_matcher = matchers.last().unwrap();
-                let new_last_matcher = last_matcher.set_follow(follow);
-                let mut new_matchers = matchers.clone();
-                new_matchers.pop();
-                new_matchers.push(new_last_matcher);
-                Matcher::Group(new_matchers, *group_idx)
-            }
-            _ => panic!("set_follow can only be called on OneOrMore or ZeroOrMore matchers"),
+            _ => None,
         }
     }
 
+    /// Tries to match `self` at `offset`. On success the continuation `k` is
+    /// called with the end offset. If the continuation rejects that end, every
+    /// other way to match (quantifier counts, alternatives) is tried in turn.
     fn check_match(&self,
-                   text: &str,
+                   input: &Input,
                    offset: usize,
-                   group_results: &HashMap<usize, String>) -> Option<Match> {
+                   group_results: &mut GroupResults,
+                   k: &mut Continuation) -> bool {
+        if stack_position().abs_diff(group_results.stack_base) > MAX_STACK_USE {
+            group_results.too_deep = true;
+        }
+        if group_results.too_deep {
+            return false;
+        }
 
         use Matcher::*;
-        match self {
-            SingleChar(ch) => self.check_single_char(*ch, text, offset),
-            StartMatcher => self.check_start(text, offset),
-            EndMatcher => self.check_end(text, offset),
-            SingleCharBranch(characters, is_negated) =>
-                self.check_single_char_branch(characters, *is_negated, text, offset),
+        let end = match self {
+            SingleChar(ch) => self.check_single_char(*ch, input, offset),
+            StartMatcher => self.check_start(input, offset),
+            EndMatcher => self.check_end(input, offset),
+            WordBoundary(unicode) =>
+                self.check_word_boundary(*unicode, input, offset, |before, after| before != after),
+            NonWordBoundary(unicode) =>
+                self.check_word_boundary(*unicode, input, offset, |before, after| before == after),
+            WordStart(unicode) =>
+                self.check_word_boundary(*unicode, input, offset, |before, after| !before && after),
+            WordEnd(unicode) =>
+                self.check_word_boundary(*unicode, input, offset, |before, after| before && !after),
+            SingleCharBranch(char_set) => self.check_single_char_branch(char_set, input, offset),
+            Wildcard => self.check_wildcard(input, offset),
+            GroupReference(group_idx, ignore_case) =>
+                self.check_group_reference(*group_idx, *ignore_case, input, offset, group_results),
             Sequence(matchers) =>
-                self.check_sequence(matchers, text, offset, group_results),
-            Multiple { matcher, min, max, follow } =>
-                self.check_multiple(matcher, *min, *max, follow, text, offset, group_results),
-            Wildcard => self.check_wildcard(text, offset),
+                return Self::check_sequence(matchers, input, offset, group_results, k),
+            Multiple { matcher, min, max } =>
+                return Self::check_multiple(matcher, *min, *max, 0, input, offset, group_results, k),
             Group(matchers, group_idx) =>
-                self.check_group(matchers, *group_idx, text, offset, group_results),
-            GroupReference(group_idx) =>
-                self.check_group_reference(*group_idx, text, offset, group_results),
+                return Self::check_group(matchers, *group_idx, input, offset, group_results, k),
+            Alternation(matchers) =>
+                return matchers.iter().any(|m| m.check_match(input, offset, group_results, k)),
+            Byte(byte) => self.check_byte(*byte, input, offset),
+            ByteBranch(char_set) => self.check_byte_branch(char_set, input, offset),
+            AnyByte => self.check_any_byte(input, offset),
+        };
+
+        match end {
+            Some(end) => k(end, group_results),
+            None => false,
         }
     }
 
-    fn check_single_char(&self, ch: char, text: &str, offset: usize) -> Option<Match> {
-        if offset >= text.chars().count() {
-            return None;
+    fn check_single_char(&self, ch: char, input: &Input, offset: usize) -> Option<usize> {
+        match input.char_at(offset) {
+            Some((c, len)) if c == ch => Some(offset + len),
+            _ => None,
         }
-        let c = text.chars().nth(offset).unwrap();
-        if c == ch {
-            Some(Match::new(&ch.to_string(), offset))
+    }
+
+    fn check_start(&self, _input: &Input, offset: usize) -> Option<usize> {
+        if offset == 0 {
+            Some(offset)
         } else {
             None
         }
     }
 
-    fn check_start(&self, _text: &str, offset: usize) -> Option<Match> {
-        if offset == 0 {
-            Some(Match::new("", offset))
+    fn check_end(&self, input: &Input, offset: usize) -> Option<usize> {
+        if offset == input.len() {
+            Some(offset)
         } else {
             None
         }
     }
 
-    fn check_end(&self, text: &str, offset: usize) -> Option<Match> {
-        if offset == text.len() {
-            Some(Match::new("", offset))
+    /// Zero-width check on the word chars around `offset`. `accept` gets
+    /// whether the char before and the char after are word chars.
+    fn check_word_boundary(&self,
+                           unicode: bool,
+                           input: &Input,
+                           offset: usize,
+                           accept: impl Fn(bool, bool) -> bool) -> Option<usize> {
+        let is_word = |c: Option<char>| c.is_some_and(|c| is_word_char(c, unicode));
+        let before = input.char_before(offset);
+        let after = input.char_at(offset).map(|(c, _)| c);
+
+        if accept(is_word(before), is_word(after)) {
+            Some(offset)
         } else {
             None
         }
     }
 
-    fn check_single_char_branch(&self,
-                                characters: &Vec<char>,
-                                is_negated: bool,
-                                text: &str,
-                                offset: usize) -> Option<Match> {
-
-        if !is_negated {
-            match text.chars().nth(offset) {
-                Some(ch) => {
-                    for c in characters {
-                        if *c == ch {
-                            return Some(Match::new(&ch.to_string(), offset));
-                        }
-                    }
-                    None
-                }
-                None => None,
-            }
+    fn check_single_char_branch(&self, char_set: &CharSet, input: &Input, offset: usize) -> Option<usize> {
+        let (ch, len) = input.char_at(offset)?;
+        if char_set.contains(ch) {
+            Some(offset + len)
         } else {
-            match text.chars().nth(offset) {
-                Some(ch) => {
-                    for c in characters {
-                        if *c == ch {
-                            return None;
-                        }
-                    }
-                    Some(Match::new(&ch.to_string(), offset))
-                }
-                None => None,
-            }
+            None
         }
     }
 
-    fn check_sequence(&self,
-                      elements: &Vec<Matcher>,
-                      text: &str,
+    fn check_sequence(elements: &[Matcher],
+                      input: &Input,
                       offset: usize,
-                      group_results:&HashMap<usize, String>) -> Option<Match> {
-        let mut curr_offset = offset;
-        let mut curr_groups = group_results.clone();
-        let mut m = Match::new("", offset);
-
-        for element in elements {
-            match element.check_match(text, curr_offset, &curr_groups) {
-                Some(other) => {
-                    m.accumulate(&other);
-                    curr_offset += other.matched_text.chars().count();
-                    Self::update_group_results(&mut curr_groups, &other);
-                }
-                None => return None
+                      group_results: &mut GroupResults,
+                      k: &mut Continuation) -> bool {
+        // Single chars leave nothing to backtrack into, so a run of them is
+        // matched in a loop instead of nesting continuations
+        let mut elements = elements;
+        let mut offset = offset;
+        while let Some((first, rest)) = elements.split_first().filter(|(first, _)| first.is_single_char()) {
+            match first.check_single(input, offset) {
+                Some(next) => offset = next,
+                None => return false,
             }
+            elements = rest;
         }
-        Some(m)
-    }
 
-    fn update_group_results(group_results: &mut HashMap<usize, String>, m: &Match) {
-        for (gid, sub_match) in &m.sub_matches {
-            group_results.insert(*gid, sub_match.matched_text.clone());
+        match elements.split_first() {
+            Some((first, rest)) => first.check_match(
+                input,
+                offset,
+                group_results,
+                &mut |next_offset, groups| Self::check_sequence(rest, input, next_offset, groups, k),
+            ),
+            None => k(offset, group_results),
         }
     }
 
-    fn check_multiple(&self,
-                      matcher: &Matcher,
+    /// Greedy repetition: first tries one more repetition of `matcher`, then
+    /// falls back to stopping after `count` repetitions.
+    #[allow(clippy::too_many_arguments)]
+    fn check_multiple(matcher: &Matcher,
                       min: usize,
                       max: Option<usize>,
-                      follow: &Option<Box<Matcher>>,
-                      text: &str,
+                      count: usize,
+                      input: &Input,
                       offset: usize,
-                      group_results: &HashMap<usize, String>) -> Option<Match> {
-        let mut curr_offset = offset;
-        let mut m = Match::new("", offset);
-        let mut curr_groups = group_results.clone();
-        let mut count = 0;
-
-        loop {
-            let min_reached = count >= min;
-            let max_reached = match max {
-                Some(max_val) => count >= max_val,
-                None => true,
-            };
-
-            match matcher.check_match(text, curr_offset, &curr_groups) {
-                Some(other) => {
-                    // If there is a following matcher that matches
-                    // stop matching to avoid "greedy" matching behavior
-                    if min_reached && max_reached && follow.is_some() &&
-                        follow.as_ref().unwrap().matches(&other.matched_text) {
-                        return Some(m);
-                    }
+                      group_results: &mut GroupResults,
+                      k: &mut Continuation) -> bool {
+        if count == 0 && matcher.is_single_char() {
+            return Self::check_single_char_repetition(matcher, min, max, input, offset, group_results, k);
+        }
 
-                    m.accumulate(&other);
-                    curr_offset += other.matched_text.chars().count();
-                    Self::update_group_results(&mut curr_groups, &other);
-                    count += 1;
+        let min_reached = count >= min;
+        let max_reached = max.is_some_and(|max_val| count >= max_val);
+
+        if !max_reached {
+            let found = matcher.check_match(input, offset, group_results, &mut |next_offset, groups| {
+                // An empty repetition of an unbounded loop ends it, like in
+                // PCRE: repeating it could never lead to a new result. Its
+                // captures are kept. Bounded counts go on, like the Pike VM,
+                // which gets a separate copy of each repetition.
+                if next_offset == offset && min_reached && max.is_none() {
+                    return k(next_offset, groups);
+                }
+                Self::check_multiple(matcher, min, max, count + 1, input, next_offset, groups, k)
+            });
+            if found {
+                return true;
+            }
+        }
 
-                    if let Some(max_val) = max {
-                        if count >= max_val {
-                            return Some(m);
-                        }
-                    }
+        min_reached && !group_results.too_deep && k(offset, group_results)
+    }
 
+    /// Repetition of a matcher that always consumes exactly one char. All
+    /// repetitions are found in a loop, then the continuation is tried from
+    /// the longest to the shortest, so long lines do not deepen the recursion.
+    fn check_single_char_repetition(matcher: &Matcher,
+                                    min: usize,
+                                    max: Option<usize>,
+                                    input: &Input,
+                                    offset: usize,
+                                    group_results: &mut GroupResults,
+                                    k: &mut Continuation) -> bool {
+        let mut ends = vec![offset];
+        let mut end = offset;
+        while max.map_or(true, |max_val| ends.len() <= max_val) {
+            match matcher.check_single(input, end) {
+                Some(next) => {
+                    end = next;
+                    ends.push(end);
                 }
-                None => {
-                    return if min_reached {
-                        Some(m)
-                    } else {
-                        None
-                    }
-                }
+                None => break,
             }
         }
+
+        ends.iter()
+            .skip(min)
+            .rev()
+            .any(|end| k(*end, group_results))
     }
 
-    fn check_wildcard(&self, text: &str, offset: usize) -> Option<Match> {
-        text.chars().nth(offset).map(|c| Match::new(&c.to_string(), offset))
+    fn is_single_char(&self) -> bool {
+        use Matcher::*;
+        matches!(self, SingleChar(_) | SingleCharBranch(_) | Wildcard | Byte(_) | ByteBranch(_) | AnyByte)
     }
 
-    fn check_group(&self,
-                   matchers: &Vec<Matcher>,
-                   group_idx: usize,
-                   text: &str,
-                   offset: usize,
-                   group_results: &HashMap<usize, String>) -> Option<Match> {
+    /// End of the char (or byte) at `offset` if it is matched by a
+    /// single-char matcher.
+    fn check_single(&self, input: &Input, offset: usize) -> Option<usize> {
+        match self {
+            Matcher::SingleChar(ch) => self.check_single_char(*ch, input, offset),
+            Matcher::SingleCharBranch(char_set) => self.check_single_char_branch(char_set, input, offset),
+            Matcher::Wildcard => self.check_wildcard(input, offset),
+            Matcher::Byte(byte) => self.check_byte(*byte, input, offset),
+            Matcher::ByteBranch(char_set) => self.check_byte_branch(char_set, input, offset),
+            Matcher::AnyByte => self.check_any_byte(input, offset),
+            _ => None,
+        }
+    }
 
-        let mut group_match = Match::new("", offset);
+    fn check_wildcard(&self, input: &Input, offset: usize) -> Option<usize> {
+        input.char_at(offset).map(|(_, len)| offset + len)
+    }
 
-        for matcher in matchers {
-            if let Some(m) = matcher.check_match(text, offset, group_results) {
-                group_match.accumulate(&m);
-                group_match.sub_matches.insert(group_idx, group_match.clone());
-                return Some(group_match);
-            }
-        }
-        None
+    fn check_byte(&self, byte: u8, input: &Input, offset: usize) -> Option<usize> {
+        (input.byte_at(offset)? == byte).then_some(offset + 1)
+    }
+
+    fn check_byte_branch(&self, char_set: &CharSet, input: &Input, offset: usize) -> Option<usize> {
+        char_set.contains_byte(input.byte_at(offset)?).then_some(offset + 1)
+    }
+
+    fn check_any_byte(&self, input: &Input, offset: usize) -> Option<usize> {
+        input.byte_at(offset).map(|_| offset + 1)
+    }
+
+    fn check_group(alternatives: &[Matcher],
+                   group_idx: usize,
+                   input: &Input,
+                   offset: usize,
+                   group_results: &mut GroupResults,
+                   k: &mut Continuation) -> bool {
+
+        alternatives.iter().any(|alternative| {
+            alternative.check_match(input, offset, group_results, &mut |end, groups| {
+                let previous = groups.spans[group_idx].replace((offset, end));
+                if k(end, groups) {
+                    return true;
+                }
+                groups.spans[group_idx] = previous;
+                false
+            })
+        })
     }
 
     fn check_group_reference(&self,
                              group_idx: usize,
-                             text: &str,
+                             ignore_case: bool,
+                             input: &Input,
                              offset: usize,
-                             group_results: &HashMap<usize, String>) -> Option<Match> {
+                             group_results: &GroupResults) -> Option<usize> {
 
-        match group_results.get(&group_idx) {
-            Some(matched) => {
-                let text = text.chars().skip(offset).collect::<String>();
-                if text.starts_with(matched) {
-                    Some(Match::new(&matched, offset))
-                } else {
-                    None
+        let (start, end) = group_results.spans[group_idx]?;
+        let matched = &input.bytes()[start..end];
+
+        if !ignore_case {
+            return input.bytes()[offset..].starts_with(matched).then_some(offset + matched.len());
+        }
+
+        // Case variants may differ in their UTF-8 length, so walk both texts.
+        // Bytes that are not valid UTF-8 (in byte mode) are compared as ASCII.
+        let (mut expected_pos, mut actual_pos) = (start, offset);
+        while expected_pos < end {
+            match (input.char_at(expected_pos), input.char_at(actual_pos)) {
+                (Some((expected, expected_len)), Some((actual, actual_len))) => {
+                    if fold_case(actual) != fold_case(expected) {
+                        return None;
+                    }
+                    expected_pos += expected_len;
+                    actual_pos += actual_len;
+                }
+                _ => {
+                    let expected = input.byte_at(expected_pos)?;
+                    if !input.byte_at(actual_pos)?.eq_ignore_ascii_case(&expected) {
+                        return None;
+                    }
+                    expected_pos += 1;
+                    actual_pos += 1;
                 }
             }
-            None => None
         }
+        Some(actual_pos)
     }
 }
 
+/// Collects all non-overlapping matches in `text`, using `find_at` to find
+/// the leftmost match at or after a position. In `byte_mode`, an empty match
+/// is followed by a search from the next byte rather than the next char.
+pub fn find_all_with(text: &str,
+                     byte_mode: bool,
+                     find_at: impl Fn(&Input, usize) -> Result<Option<Captures>, MatchError>) -> Result<Vec<Match>, MatchError> {
+    let matches = find_all_captures(&Input::new(text.as_bytes()), byte_mode, find_at)?
+        .iter()
+        .map(|captures| Match::from_captures(text, captures))
+        .collect();
+    Ok(matches)
+}
+
+/// Like `find_all_with`, but returns the captures of the matches.
+pub fn find_all_captures(input: &Input,
+                         byte_mode: bool,
+                         find_at: impl Fn(&Input, usize) -> Result<Option<Captures>, MatchError>) -> Result<Vec<Captures>, MatchError> {
+    let mut ret = vec![];
+    let mut offset = 0;
+    while offset <= input.len() {
+        let Some(captures) = find_at(input, offset)? else {
+            break;
+        };
+        let (start, end) = captures[0].unwrap();
+        // Empty matches must not stall the search
+        offset = match (end > start, byte_mode) {
+            (true, _) => end,
+            (false, true) => end + 1,
+            (false, false) => end + input.step_len(end),
+        };
+        ret.push(captures);
+    }
+    Ok(ret)
+}
+
+/// A match in a text. `offset` is the byte offset of the matched text, so
+/// `offset..end()` is its byte span in the searched text.
+///
+/// Byte mode patterns can match part of a char; such a split char shows up
+/// as U+FFFD in `matched_text`.
 #[derive(Debug, Clone)]
 pub struct Match {
     pub matched_text: String,
     pub offset: usize,
     pub sub_matches: HashMap<usize, Match>,
+    end: usize,
 }
 
 impl Match {
-    fn new(matched_text: &str, offset: usize) -> Self {
+    fn new(text: &str, start: usize, end: usize) -> Self {
         Self {
-            matched_text: matched_text.to_string(),
-            offset,
+            matched_text: String::from_utf8_lossy(&text.as_bytes()[start..end]).into_owned(),
+            offset: start,
             sub_matches: HashMap::new(),
+            end,
+        }
+    }
+
+    /// Builds the match for `captures` found in `text`.
+    pub fn from_captures(text: &str, captures: &[Option<(usize, usize)>]) -> Self {
+        let (start, end) = captures[0].expect("captures of a match contain its span");
+        let mut m = Match::new(text, start, end);
+        for (group_idx, span) in captures.iter().enumerate().skip(1) {
+            if let Some((start, end)) = *span {
+                m.sub_matches.insert(group_idx, Match::new(text, start, end));
+            }
         }
+        m
     }
 
-    fn accumulate(&mut self, other: &Match) {
-        self.matched_text.push_str(&other.matched_text);
-        for (group_idx, sub_match) in &other.sub_matches {
-            self.sub_matches.insert(*group_idx, sub_match.clone());
+    /// Byte offset just past the matched text.
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    pub fn span(&self) -> std::ops::Range<usize> {
+        self.offset..self.end()
+    }
+
+}
+
+/// Named character classes usable on their own (`\d`) or inside brackets
+/// (`[[:alpha:]]`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CharClass {
+    Alnum,
+    Alpha,
+    Blank,
+    Cntrl,
+    Digit,
+    Graph,
+    Lower,
+    Print,
+    Punct,
+    Space,
+    Upper,
+    Word,
+    XDigit,
+}
+
+impl CharClass {
+    /// Looks up a POSIX class by the name used in `[[:name:]]`.
+    pub fn from_posix_name(name: &str) -> Option<Self> {
+        use CharClass::*;
+        let class = match name {
+            "alnum" => Alnum,
+            "alpha" => Alpha,
+            "blank" => Blank,
+            "cntrl" => Cntrl,
+            "digit" => Digit,
+            "graph" => Graph,
+            "lower" => Lower,
+            "print" => Print,
+            "punct" => Punct,
+            "space" => Space,
+            "upper" => Upper,
+            "xdigit" => XDigit,
+            _ => return None,
+        };
+        Some(class)
+    }
+
+    pub fn matches(&self, c: char) -> bool {
+        use CharClass::*;
+        match self {
+            Alnum => c.is_alphanumeric(),
+            Alpha => c.is_alphabetic(),
+            Blank => c == ' ' || c == '\t',
+            Cntrl => c.is_control(),
+            Digit => c.is_ascii_digit(),
+            Graph => !c.is_whitespace() && !c.is_control(),
+            Lower => c.is_lowercase(),
+            Print => c == ' ' || Graph.matches(c),
+            Punct => c.is_ascii_punctuation() || (!c.is_ascii() && Graph.matches(c) && !c.is_alphanumeric()),
+            Space => matches!(c, ' ' | '\t' | '\n' | '\r' | '\x0B' | '\x0C'),
+            Upper => c.is_uppercase(),
+            Word => c.is_alphanumeric() || c == '_',
+            XDigit => c.is_ascii_hexdigit(),
         }
     }
+}
 
+/// Whether `c` counts as part of a word for `\b` and friends. In byte mode
+/// only ASCII chars do, like for `\w`.
+pub fn is_word_char(c: char, unicode: bool) -> bool {
+    (unicode || c.is_ascii()) && CharClass::Word.matches(c)
+}
+
+/// A single member of a bracket expression.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SetItem {
+    Char(char),
+    Range(char, char),
+    Class(CharClass),
+    NegatedClass(CharClass),
+}
+
+impl SetItem {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            SetItem::Char(ch) => c == *ch,
+            SetItem::Range(from, to) => (*from..=*to).contains(&c),
+            SetItem::Class(class) => class.matches(c),
+            SetItem::NegatedClass(class) => !class.matches(c),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CharSet {
+    pub items: Vec<SetItem>,
+    pub negated: bool,
+    pub ignore_case: bool,
+}
+
+impl CharSet {
+    pub fn contains(&self, c: char) -> bool {
+        let found = if self.ignore_case {
+            self.items.iter().any(|item| match item {
+                SetItem::Char(ch) => fold_case(*ch) == fold_case(c),
+                _ => case_variants(c).any(|variant| item.matches(variant)),
+            })
+        } else {
+            self.items.iter().any(|item| item.matches(c))
+        };
+        found != self.negated
+    }
+
+    /// Byte mode membership: ASCII bytes are looked up as chars, higher
+    /// bytes only match chars and ranges up to U+00FF by their code point.
+    pub fn contains_byte(&self, byte: u8) -> bool {
+        if byte.is_ascii() {
+            return self.contains(byte as char);
+        }
+        let c = byte as char;
+        let found = self.items.iter().any(|item| match item {
+            SetItem::Char(_) | SetItem::Range(..) => item.matches(c),
+            SetItem::Class(_) => false,
+            SetItem::NegatedClass(_) => true,
+        });
+        found != self.negated
+    }
+}
+
+/// Simple Unicode case folding: maps every char to one representative of
+/// the chars that are equal ignoring case.
+pub fn fold_case(c: char) -> char {
+    let upper = single_char(c.to_uppercase()).unwrap_or(c);
+    single_char(upper.to_lowercase())
+        .or_else(|| single_char(c.to_lowercase()))
+        .unwrap_or(c)
+}
+
+fn case_variants(c: char) -> impl Iterator<Item = char> + Clone {
+    let lower = single_char(c.to_lowercase()).unwrap_or(c);
+    let upper = single_char(c.to_uppercase()).unwrap_or(c);
+    [c, lower, upper, fold_case(c)].into_iter()
+}
+
+fn single_char(mut chars: impl Iterator<Item = char>) -> Option<char> {
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
 }
 
 pub fn make_digit_matcher() -> Matcher {
-    let digits = vec!['0', '1', '2', '3', '4', '5', '6', '7', '8', '9'];
-    Matcher::new_single_char_branch(digits, false)
+    make_class_matcher(CharClass::Digit, false)
+}
+
+pub fn make_non_digit_matcher() -> Matcher {
+    make_class_matcher(CharClass::Digit, true)
 }
 
 pub fn make_alpha_num_matcher() -> Matcher {
-    let lower_chars = "abcdefghijklmnopqrstuvwxyz";
-    let upper_chars = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
-    let digits = "0123456789";
+    make_class_matcher(CharClass::Word, false)
+}
 
-    let mut alpha_nums = lower_chars.to_string();
-    alpha_nums.push_str(&upper_chars);
-    alpha_nums.push_str(&digits);
-    alpha_nums.push('_');
+pub fn make_non_alpha_num_matcher() -> Matcher {
+    make_class_matcher(CharClass::Word, true)
+}
+
+pub fn make_whitespace_matcher() -> Matcher {
+    make_class_matcher(CharClass::Space, false)
+}
+
+pub fn make_non_whitespace_matcher() -> Matcher {
+    make_class_matcher(CharClass::Space, true)
+}
 
-    Matcher::new_single_char_branch(alpha_nums.chars().collect(), false)
+fn make_class_matcher(class: CharClass, negated: bool) -> Matcher {
+    Matcher::new_single_char_branch(vec![SetItem::Class(class)], negated, false)
 }
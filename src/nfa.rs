@@ -0,0 +1,233 @@
+use crate::input::Input;
+use crate::matcher::{is_word_char, CharSet, Matcher};
+
+/// Programs larger than this are not worth compiling; such patterns (mostly
+/// huge counted repetitions) stay with the backtracker.
+const MAX_PROGRAM_LEN: usize = 50_000;
+
+/// Zero-width conditions on the position in the input.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Assertion {
+    Start,
+    End,
+    /// The word assertions hold the `unicode` flag, see `Matcher`.
+    WordBoundary(bool),
+    NonWordBoundary(bool),
+    WordStart(bool),
+    WordEnd(bool),
+}
+
+impl Assertion {
+    pub fn holds(&self, input: &Input, pos: usize) -> bool {
+        let is_word = |c: Option<char>, unicode| c.is_some_and(|c| is_word_char(c, unicode));
+        let before = |unicode| is_word(input.char_before(pos), unicode);
+        let after = |unicode| is_word(input.char_at(pos).map(|(c, _)| c), unicode);
+
+        match *self {
+            Assertion::Start => pos == 0,
+            Assertion::End => pos == input.len(),
+            Assertion::WordBoundary(unicode) => before(unicode) != after(unicode),
+            Assertion::NonWordBoundary(unicode) => before(unicode) == after(unicode),
+            Assertion::WordStart(unicode) => !before(unicode) && after(unicode),
+            Assertion::WordEnd(unicode) => before(unicode) && !after(unicode),
+        }
+    }
+}
+
+/// One instruction of a Thompson NFA.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Inst {
+    Char(char),
+    Set(CharSet),
+    Any,
+    Byte(u8),
+    ByteSet(CharSet),
+    AnyByte,
+    Assert(Assertion),
+    /// Continue at both targets, preferring the first.
+    Split(usize, usize),
+    Jmp(usize),
+    /// Record the current position in a capture slot.
+    Save(usize),
+    Match,
+}
+
+impl Inst {
+    /// Whether this instruction consumes the char `c`. Only meaningful for
+    /// `Char`, `Set` and `Any`.
+    pub fn accepts(&self, c: char) -> bool {
+        match self {
+            Inst::Char(ch) => *ch == c,
+            Inst::Set(char_set) => char_set.contains(c),
+            Inst::Any => true,
+            _ => false,
+        }
+    }
+
+    /// Whether this instruction consumes the byte `byte` in a byte mode
+    /// program. Only meaningful for `Char` (ASCII only there), `Byte`,
+    /// `ByteSet` and `AnyByte`.
+    pub fn accepts_byte(&self, byte: u8) -> bool {
+        match self {
+            Inst::Char(ch) => *ch == byte as char,
+            Inst::Byte(b) => *b == byte,
+            Inst::ByteSet(char_set) => char_set.contains_byte(byte),
+            Inst::AnyByte => true,
+            _ => false,
+        }
+    }
+
+    /// Whether this instruction may consume a multi-byte char.
+    fn consumes_chars(&self) -> bool {
+        match self {
+            Inst::Char(ch) => !ch.is_ascii(),
+            Inst::Set(_) | Inst::Any => true,
+            _ => false,
+        }
+    }
+}
+
+/// A `Matcher` tree compiled to a flat list of NFA instructions.
+///
+/// Group `i` records its span in slots `2 * i` and `2 * i + 1`; slots 0 and
+/// 1 hold the span of the whole match.
+///
+/// A program either steps through the input char by char or, if it uses
+/// byte mode instructions, byte by byte.
+#[derive(Clone, Debug)]
+pub struct Program {
+    pub insts: Vec<Inst>,
+    pub slot_count: usize,
+    pub anchored: bool,
+    pub byte_mode: bool,
+}
+
+impl Program {
+    /// Compiles `matcher`, or returns `None` if it cannot be expressed as an
+    /// NFA because it uses backreferences, mixes byte mode with instructions
+    /// consuming multi-byte chars (or would be too large).
+    pub fn compile(matcher: &Matcher) -> Option<Program> {
+        let mut compiler = Compiler { insts: vec![] };
+        compiler.push(Inst::Save(0));
+        compiler.compile(matcher)?;
+        compiler.push(Inst::Save(1));
+        compiler.push(Inst::Match);
+
+        let byte_mode = compiler.insts.iter().any(|inst| matches!(inst, Inst::Byte(_) | Inst::ByteSet(_) | Inst::AnyByte));
+        if byte_mode && compiler.insts.iter().any(Inst::consumes_chars) {
+            return None;
+        }
+
+        Some(Program {
+            insts: compiler.insts,
+            slot_count: 2 * (matcher.group_count() + 1),
+            anchored: matcher.is_anchored_start(),
+            byte_mode,
+        })
+    }
+}
+
+struct Compiler {
+    insts: Vec<Inst>,
+}
+
+impl Compiler {
+    fn compile(&mut self, matcher: &Matcher) -> Option<()> {
+        use Matcher::*;
+
+        if self.insts.len() > MAX_PROGRAM_LEN {
+            return None;
+        }
+
+        match matcher {
+            SingleChar(ch) => self.push(Inst::Char(*ch)),
+            SingleCharBranch(char_set) => self.push(Inst::Set(char_set.clone())),
+            Wildcard => self.push(Inst::Any),
+            Byte(byte) => self.push(Inst::Byte(*byte)),
+            ByteBranch(char_set) => self.push(Inst::ByteSet(char_set.clone())),
+            AnyByte => self.push(Inst::AnyByte),
+            StartMatcher => self.push(Inst::Assert(Assertion::Start)),
+            EndMatcher => self.push(Inst::Assert(Assertion::End)),
+            WordBoundary(unicode) => self.push(Inst::Assert(Assertion::WordBoundary(*unicode))),
+            NonWordBoundary(unicode) => self.push(Inst::Assert(Assertion::NonWordBoundary(*unicode))),
+            WordStart(unicode) => self.push(Inst::Assert(Assertion::WordStart(*unicode))),
+            WordEnd(unicode) => self.push(Inst::Assert(Assertion::WordEnd(*unicode))),
+            Sequence(matchers) => {
+                for matcher in matchers {
+                    self.compile(matcher)?;
+                }
+            }
+            Group(alternatives, group_idx) => {
+                self.push(Inst::Save(2 * group_idx));
+                self.compile_alternation(alternatives)?;
+                self.push(Inst::Save(2 * group_idx + 1));
+            }
+            Alternation(alternatives) => self.compile_alternation(alternatives)?,
+            Multiple { matcher, min, max } => self.compile_repetition(matcher, *min, *max)?,
+            GroupReference(..) => return None,
+        }
+        Some(())
+    }
+
+    fn compile_alternation(&mut self, alternatives: &[Matcher]) -> Option<()> {
+        let mut jumps_to_end = vec![];
+
+        for (idx, alternative) in alternatives.iter().enumerate() {
+            if idx + 1 == alternatives.len() {
+                self.compile(alternative)?;
+                break;
+            }
+            let split = self.push_placeholder();
+            self.compile(alternative)?;
+            jumps_to_end.push(self.push_placeholder());
+            let next = self.insts.len();
+            self.insts[split] = Inst::Split(split + 1, next);
+        }
+
+        let end = self.insts.len();
+        for jump in jumps_to_end {
+            self.insts[jump] = Inst::Jmp(end);
+        }
+        Some(())
+    }
+
+    fn compile_repetition(&mut self, matcher: &Matcher, min: usize, max: Option<usize>) -> Option<()> {
+        for _ in 0..min {
+            self.compile(matcher)?;
+        }
+
+        match max {
+            None => {
+                // loop: Split(body, exit); body; Jmp(loop)
+                let split = self.push_placeholder();
+                self.compile(matcher)?;
+                self.push(Inst::Jmp(split));
+                let exit = self.insts.len();
+                self.insts[split] = Inst::Split(split + 1, exit);
+            }
+            Some(max) => {
+                // Each optional repetition may skip all remaining ones
+                let mut splits = vec![];
+                for _ in min..max {
+                    splits.push(self.push_placeholder());
+                    self.compile(matcher)?;
+                }
+                let exit = self.insts.len();
+                for split in splits {
+                    self.insts[split] = Inst::Split(split + 1, exit);
+                }
+            }
+        }
+        Some(())
+    }
+
+    fn push(&mut self, inst: Inst) {
+        self.insts.push(inst);
+    }
+
+    /// Reserves an instruction to be patched once its targets are known.
+    fn push_placeholder(&mut self) -> usize {
+        self.insts.push(Inst::Match);
+        self.insts.len() - 1
+    }
+}
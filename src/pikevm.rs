@@ -0,0 +1,275 @@
+use crate::input::Input;
+use crate::matcher::Captures;
+use crate::nfa::{Inst, Program};
+
+type Slots = Vec<Option<usize>>;
+
+/// Runs a `Program` as a Pike VM: all NFA threads advance in lock step over
+/// the input, so a search takes time linear in the length of the input.
+///
+/// Threads are kept in priority order, which gives the same leftmost-first
+/// results (including captures) as the backtracker. The one exception is an
+/// unbounded repetition whose body matches the empty string: the backtracker
+/// ends the loop with one empty iteration and keeps its captures, like PCRE,
+/// but each instruction is only explored once per position here, so that
+/// thread is dropped. The match is the same, but its captures can differ.
+pub struct PikeVm<'p> {
+    program: &'p Program,
+}
+
+/// The threads alive at one position. `pcs` lists the threads waiting on
+/// input (or at `Match`) in priority order; `visited` covers every
+/// instruction reached while adding them.
+struct Threads {
+    pcs: Vec<usize>,
+    visited: Vec<bool>,
+    visited_pcs: Vec<usize>,
+    slots: Vec<Slots>,
+}
+
+impl Threads {
+    fn new(program: &Program) -> Self {
+        Self {
+            pcs: Vec::with_capacity(program.insts.len()),
+            visited: vec![false; program.insts.len()],
+            visited_pcs: Vec::with_capacity(program.insts.len()),
+            slots: vec![vec![]; program.insts.len()],
+        }
+    }
+
+    fn clear(&mut self) {
+        self.pcs.clear();
+        for pc in self.visited_pcs.drain(..) {
+            self.visited[pc] = false;
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Unit {
+    Char(char, usize),
+    Byte(u8),
+}
+
+/// Work items for following epsilon transitions without recursion.
+enum Frame {
+    Explore(usize),
+    RestoreSlot(usize, Option<usize>),
+}
+
+impl<'p> PikeVm<'p> {
+    pub fn new(program: &'p Program) -> Self {
+        Self { program }
+    }
+
+    /// Finds the leftmost match starting at or after byte offset `start`.
+    pub fn find_at(&self, input: &Input, start: usize) -> Option<Captures> {
+        let mut current = Threads::new(self.program);
+        let mut next = Threads::new(self.program);
+        let mut slots = vec![None; self.program.slot_count];
+        let mut stack = vec![];
+        let mut matched: Option<Slots> = None;
+        let mut pos = start;
+
+        loop {
+            let may_start = matched.is_none() && (pos == start || !self.program.anchored);
+            if may_start {
+                slots.fill(None);
+                self.add_thread(&mut current, 0, input, pos, &mut slots, &mut stack);
+            }
+            // Without threads, only a later start can still match
+            let may_start_later = matched.is_none() && !self.program.anchored;
+            if current.pcs.is_empty() && !may_start_later {
+                break;
+            }
+
+            let unit = self.unit_at(input, pos);
+            for idx in 0..current.pcs.len() {
+                let pc = current.pcs[idx];
+                match &self.program.insts[pc] {
+                    Inst::Match => {
+                        matched = Some(current.slots[pc].clone());
+                        // Lower priority threads cannot win any more
+                        break;
+                    }
+                    inst => {
+                        if let Some(len) = unit.and_then(|unit| self.consumes(inst, unit)) {
+                            let mut thread_slots = std::mem::take(&mut current.slots[pc]);
+                            self.add_thread(&mut next, pc + 1, input, pos + len, &mut thread_slots, &mut stack);
+                        }
+                    }
+                }
+            }
+
+            if pos >= input.len() {
+                break;
+            }
+            pos += if self.program.byte_mode { 1 } else { input.step_len(pos) };
+            std::mem::swap(&mut current, &mut next);
+            next.clear();
+        }
+
+        matched.map(|slots| {
+            slots
+                .chunks(2)
+                .map(|span| match span {
+                    [Some(start), Some(end)] => Some((*start, *end)),
+                    _ => None,
+                })
+                .collect()
+        })
+    }
+
+    /// The input at `pos` as seen by the program: a char, or a single byte
+    /// in byte mode.
+    fn unit_at(&self, input: &Input, pos: usize) -> Option<Unit> {
+        if self.program.byte_mode {
+            input.byte_at(pos).map(Unit::Byte)
+        } else {
+            input.char_at(pos).map(|(c, len)| Unit::Char(c, len))
+        }
+    }
+
+    /// Length of `unit` if `inst` consumes it.
+    fn consumes(&self, inst: &Inst, unit: Unit) -> Option<usize> {
+        match unit {
+            Unit::Char(c, len) => inst.accepts(c).then_some(len),
+            Unit::Byte(byte) => inst.accepts_byte(byte).then_some(1),
+        }
+    }
+
+    /// Adds the thread at `pc` to `threads`, following jumps, splits, saves
+    /// and assertions until it reaches instructions that consume input.
+    fn add_thread(&self,
+                  threads: &mut Threads,
+                  pc: usize,
+                  input: &Input,
+                  pos: usize,
+                  slots: &mut Slots,
+                  stack: &mut Vec<Frame>) {
+        stack.push(Frame::Explore(pc));
+
+        while let Some(frame) = stack.pop() {
+            let pc = match frame {
+                Frame::Explore(pc) => pc,
+                Frame::RestoreSlot(slot, value) => {
+                    slots[slot] = value;
+                    continue;
+                }
+            };
+            if threads.visited[pc] {
+                continue;
+            }
+            threads.visited[pc] = true;
+            threads.visited_pcs.push(pc);
+
+            match &self.program.insts[pc] {
+                Inst::Jmp(target) => stack.push(Frame::Explore(*target)),
+                Inst::Split(first, second) => {
+                    stack.push(Frame::Explore(*second));
+                    stack.push(Frame::Explore(*first));
+                }
+                Inst::Save(slot) => {
+                    stack.push(Frame::RestoreSlot(*slot, slots[*slot]));
+                    slots[*slot] = Some(pos);
+                    stack.push(Frame::Explore(pc + 1));
+                }
+                Inst::Assert(assertion) => {
+                    if assertion.holds(input, pos) {
+                        stack.push(Frame::Explore(pc + 1));
+                    }
+                }
+                _ => {
+                    threads.pcs.push(pc);
+                    threads.slots[pc].clone_from(slots);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::regex_parser::RegexParser;
+
+    fn compile(pattern: &str) -> (crate::matcher::Matcher, Program) {
+        let matcher = RegexParser::new(pattern).parse().unwrap();
+        let program = Program::compile(&matcher).unwrap();
+        (matcher, program)
+    }
+
+    #[test]
+    fn test_agrees_with_backtracker() {
+        let cases = [
+            ("a.*b.*c", "xaxxbxxcxbxc"),
+            ("(a|ab)(c|bcd)(d*)", "abcd"),
+            (r"(\w+)@(\w+)\.com", "mail bob@example.com now"),
+            ("ro{2,3}m", "vrooooom rooom"),
+            (r"\bfoo\b|bar", "foobar bar foo"),
+            ("^(ab|a)(b*)$", "abbb"),
+            ("(?i:grüße)+", "GRÜßEgrüße!"),
+            ("[^[:alpha:]]+", "ab12 ;cd"),
+            ("x*", "äö"),
+            ("", "abc"),
+            (r"(?-u)(\xC3.)+", "aäöb"),
+            (r"(?-u)\bö.", "ab öx"),
+            (r"\<über\b", "xüber über"),
+            (r"\b\d+\b", " 42 apples"),
+            (r"\<\w+\>", "(id) x"),
+            (r"\b\w", "  ab"),
+            ("$", "abc"),
+            (r"^([^a]?|\w+){1,2}", "a"),
+            ("(a*|.*){1,2}b*", "abc"),
+            ("(a|b?){2,3}c", "abbc"),
+            ("(a|b?)*x", "aab"),
+        ];
+
+        for (pattern, text) in cases {
+            let (matcher, program) = compile(pattern);
+            let input = Input::new(text.as_bytes());
+            for start in (0..=text.len()).filter(|idx| text.is_char_boundary(*idx)) {
+                assert_eq!(
+                    PikeVm::new(&program).find_at(&input, start),
+                    matcher.find_at(&input, start).unwrap(),
+                    "pattern {pattern:?} on {text:?} from {start}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_empty_iterations_give_the_same_match() {
+        let cases = [("(a*)*b", "aaab"), ("(x?)*y", "xxy"), ("(b?)+", "ab")];
+
+        for (pattern, text) in cases {
+            let (matcher, program) = compile(pattern);
+            let input = Input::new(text.as_bytes());
+            for start in 0..=text.len() {
+                assert_eq!(
+                    PikeVm::new(&program).find_at(&input, start).map(|captures| captures[0]),
+                    matcher.find_at(&input, start).unwrap().map(|captures| captures[0]),
+                    "pattern {pattern:?} on {text:?} from {start}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_backreferences_are_not_compiled() {
+        let matcher = RegexParser::new(r"(a)\1").parse().unwrap();
+        assert!(Program::compile(&matcher).is_none());
+    }
+
+    #[test]
+    fn test_nested_quantifiers_run_in_linear_time() {
+        let (_, program) = compile("(a*)*b");
+        let text = "a".repeat(100_000);
+        assert!(PikeVm::new(&program).find_at(&Input::new(text.as_bytes()), 0).is_none());
+
+        let (_, program) = compile("(x+x+)+y");
+        let text = format!("{}y", "x".repeat(10_000));
+        let captures = PikeVm::new(&program).find_at(&Input::new(text.as_bytes()), 0).unwrap();
+        assert_eq!(captures[0], Some((0, text.len())));
+    }
+}
@@ -0,0 +1,317 @@
+use std::fmt;
+use std::ops::Range;
+use std::sync::{Mutex, PoisonError};
+use crate::dfa::LazyDfa;
+use crate::error::{MatchError, RegexError};
+use crate::input::Input;
+use crate::literal::{self, Literals};
+use crate::matcher::{find_all_captures, find_all_with, Captures, Match, Matcher};
+use crate::nfa::Program;
+use crate::pikevm::PikeVm;
+use crate::regex_parser::RegexParser;
+
+/// A pattern that could not be parsed.
+///
+/// The `Display` output shows the pattern with carets under the span where
+/// parsing failed. The underlying [`RegexError`] is available as `error`.
+#[derive(Debug)]
+pub struct PatternError {
+    pub pattern: String,
+    pub error: RegexError,
+}
+
+impl PatternError {
+    /// Char index in the pattern where the error starts.
+    pub fn index(&self) -> usize {
+        self.error.span().start
+    }
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let span = self.error.span();
+        let width = span.end.saturating_sub(span.start).max(1);
+        writeln!(f, "invalid pattern: {}", self.error)?;
+        writeln!(f, "  {}", self.pattern)?;
+        write!(f, "  {}{}", " ".repeat(span.start), "^".repeat(width))
+    }
+}
+
+impl std::error::Error for PatternError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// A compiled regular expression.
+///
+/// The pattern is parsed once on construction, so a `Regex` can be reused
+/// for any number of lines without paying for the parser again.
+///
+/// Patterns without backreferences are compiled to an NFA and run on a Pike
+/// VM in linear time; the others use the backtracking `Matcher`. Both find
+/// the same matches, with the same captures outside of empty iterations of
+/// unbounded repetitions (see `PikeVm`). Yes/no queries via
+/// [`Regex::is_match`] run on a lazy DFA where possible.
+///
+/// Searches fail with a [`MatchError`] if the backtracker would need too
+/// much stack for the text, rather than answering without knowing.
+///
+/// The `_bytes` methods search text that need not be UTF-8, see `(?-u)` for
+/// matching arbitrary bytes.
+///
+/// A `Regex` can be shared between threads. The DFA cache is only used by
+/// one thread at a time; the others answer on the Pike VM meanwhile.
+///
+/// Lines lacking a literal that every match contains are rejected up front,
+/// and a literal prefix is used to jump straight to candidate offsets.
+#[derive(Debug, Clone)]
+pub struct Regex {
+    pattern: String,
+    matcher: Matcher,
+    program: Option<Program>,
+    dfa: Option<SharedDfa>,
+    literals: Literals,
+}
+
+#[derive(Debug)]
+struct SharedDfa(Mutex<LazyDfa>);
+
+impl Clone for SharedDfa {
+    fn clone(&self) -> Self {
+        let dfa = self.0.lock().unwrap_or_else(PoisonError::into_inner).clone();
+        Self(Mutex::new(dfa))
+    }
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Regex, PatternError> {
+        Self::new_with_ignore_case(pattern, false)
+    }
+
+    /// Compiles `pattern` so that it matches regardless of case, as if it
+    /// started with `(?i)`.
+    pub fn new_with_ignore_case(pattern: &str, ignore_case: bool) -> Result<Regex, PatternError> {
+        let mut parser = RegexParser::new(pattern).with_ignore_case(ignore_case);
+        let matcher = parser.parse().map_err(|error| PatternError {
+            pattern: pattern.to_string(),
+            error,
+        })?;
+        let program = Program::compile(&matcher);
+        let dfa = program.as_ref().and_then(LazyDfa::new).map(|dfa| SharedDfa(Mutex::new(dfa)));
+        let mut literals = Literals::extract(&matcher);
+        if matcher.is_anchored_start() {
+            // There is only one offset to try anyway
+            literals.prefix.clear();
+        }
+        Ok(Self {
+            pattern: pattern.to_string(),
+            matcher,
+            program,
+            dfa,
+            literals,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Whether `text` contains a match. Cheaper than [`Regex::find`] as no
+    /// spans or captures are tracked.
+    pub fn is_match(&self, text: &str) -> Result<bool, MatchError> {
+        self.is_match_bytes(text.as_bytes())
+    }
+
+    pub fn find(&self, text: &str) -> Result<Option<Match>, MatchError> {
+        if !self.may_match(text.as_bytes()) {
+            return Ok(None);
+        }
+        let captures = self.find_at(&Input::new(text.as_bytes()), 0)?;
+        Ok(captures.map(|captures| Match::from_captures(text, &captures)))
+    }
+
+    pub fn find_all(&self, text: &str) -> Result<Vec<Match>, MatchError> {
+        if !self.may_match(text.as_bytes()) {
+            return Ok(vec![]);
+        }
+        find_all_with(text, self.matcher.uses_bytes(), |input, start| self.find_at(input, start))
+    }
+
+    /// Like [`Regex::is_match`], for text that need not be valid UTF-8.
+    /// Outside of byte mode, invalid bytes are not matched by anything.
+    pub fn is_match_bytes(&self, haystack: &[u8]) -> Result<bool, MatchError> {
+        if !self.may_match(haystack) {
+            return Ok(false);
+        }
+        let input = Input::new(haystack);
+        let dfa_result = self.dfa.as_ref().and_then(|dfa| dfa.0.try_lock().ok()?.is_match(&input));
+        match dfa_result {
+            Some(matched) => Ok(matched),
+            None => Ok(self.find_at(&input, 0)?.is_some()),
+        }
+    }
+
+    /// Byte span of the leftmost match in `haystack`.
+    pub fn find_bytes(&self, haystack: &[u8]) -> Result<Option<Range<usize>>, MatchError> {
+        if !self.may_match(haystack) {
+            return Ok(None);
+        }
+        let captures = self.find_at(&Input::new(haystack), 0)?;
+        Ok(captures.and_then(|captures| captures[0]).map(|(start, end)| start..end))
+    }
+
+    /// Byte spans of all non-overlapping matches in `haystack`.
+    pub fn find_all_bytes(&self, haystack: &[u8]) -> Result<Vec<Range<usize>>, MatchError> {
+        if !self.may_match(haystack) {
+            return Ok(vec![]);
+        }
+        let input = Input::new(haystack);
+        let matches = find_all_captures(&input, self.matcher.uses_bytes(), |input, start| self.find_at(input, start))?
+            .iter()
+            .filter_map(|captures| captures[0].map(|(start, end)| start..end))
+            .collect();
+        Ok(matches)
+    }
+
+    /// Quick check that `haystack` contains the literal every match requires.
+    fn may_match(&self, haystack: &[u8]) -> bool {
+        literal::find(haystack, &self.literals.required).is_some()
+    }
+
+    fn find_at(&self, input: &Input, start: usize) -> Result<Option<Captures>, MatchError> {
+        let prefix = &self.literals.prefix[..];
+        let next_candidate = |pos: usize| {
+            let skipped = literal::find(&input.bytes()[pos.min(input.len())..], prefix)?;
+            Some(pos + skipped)
+        };
+
+        match &self.program {
+            // The Pike VM tries all later offsets in the same pass
+            Some(program) => Ok(next_candidate(start).and_then(|pos| PikeVm::new(program).find_at(input, pos))),
+            None if prefix.is_empty() => self.matcher.find_at(input, start),
+            None => {
+                let mut pos = start;
+                while let Some(candidate) = next_candidate(pos) {
+                    if let Some(captures) = self.matcher.find_anchored_at(input, candidate)? {
+                        return Ok(Some(captures));
+                    }
+                    pos = candidate + 1;
+                }
+                Ok(None)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Span;
+
+    #[test]
+    fn test_regex_is_reusable() {
+        let regex = Regex::new(r"\d+").unwrap();
+        assert!(regex.is_match("abc 123").unwrap());
+        assert!(!regex.is_match("abc").unwrap());
+        assert_eq!(regex.find_all("1 22 333").unwrap().len(), 3);
+        assert_eq!(regex.as_str(), r"\d+");
+    }
+
+    #[test]
+    fn test_regex_is_shared_between_threads() {
+        let regex = Regex::new("a.c").unwrap();
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..1_000 {
+                        assert!(regex.is_match("xabc").unwrap());
+                        assert!(!regex.is_match("ab").unwrap());
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        let err = Regex::new("(a|)").unwrap_err();
+        assert_eq!(err.index(), 3);
+
+        let err = Regex::new("x(ab|(c|))").unwrap_err();
+        assert_eq!(err.index(), 8);
+        assert_eq!(err.to_string(), "invalid pattern: empty alternative\n  x(ab|(c|))\n          ^");
+
+        let err = Regex::new("ab[cd").unwrap_err();
+        assert_eq!(err.error, RegexError::UnclosedBracket { span: Span::new(2, 5) });
+        assert_eq!(err.to_string(), "invalid pattern: unterminated character class '['\n  ab[cd\n    ^^^");
+
+        let err = Regex::new("+a").unwrap_err();
+        assert_eq!(err.index(), 0);
+    }
+
+    #[test]
+    fn test_empty_pattern_matches_everything() {
+        let regex = Regex::new("").unwrap();
+        assert!(regex.is_match("").unwrap());
+        assert!(regex.is_match("abc").unwrap());
+    }
+
+    #[test]
+    fn test_assertion_failing_at_start() {
+        let regex = Regex::new(r"\b\d+\b").unwrap();
+        assert!(regex.is_match(" 42 apples").unwrap());
+        assert_eq!(regex.find_all_bytes(b" 42 apples 7").unwrap(), [1..3, 11..12]);
+        assert!(Regex::new(r"\<\w+\>").unwrap().is_match("(id) x").unwrap());
+        assert_eq!(Regex::new("$").unwrap().find_bytes(b"abc").unwrap(), Some(3..3));
+    }
+
+    #[test]
+    fn test_literal_prefilter() {
+        let regex = Regex::new(r"ERROR (\d+)").unwrap();
+        assert!(!regex.is_match("WARN 12").unwrap());
+        let spans: Vec<_> = regex.find_all("ERROR x ERROR 1 ERROR 22").unwrap().iter().map(Match::span).collect();
+        assert_eq!(spans, [8..15, 16..24]);
+
+        // Same with the backtracker
+        let regex = Regex::new(r"ab(c)\1").unwrap();
+        assert!(!regex.is_match("abab").unwrap());
+        let spans: Vec<_> = regex.find_all("abcab abcc äabcc").unwrap().iter().map(Match::span).collect();
+        assert_eq!(spans, [6..10, 13..17]);
+    }
+
+    #[test]
+    fn test_byte_mode() {
+        let regex = Regex::new(r"(?-u)\xFF").unwrap();
+        assert_eq!(regex.find_bytes(b"a\xffb").unwrap(), Some(1..2));
+        assert!(!regex.is_match("ÿ").unwrap());
+        let regex = Regex::new(r"\xFF").unwrap();
+        assert!(regex.is_match("ÿ").unwrap());
+        assert!(!regex.is_match_bytes(b"\xff").unwrap());
+
+        let regex = Regex::new(r"(?-u)[\x80-\xFF]+").unwrap();
+        assert_eq!(regex.find_all_bytes(b"ab\xe9\xe8c\xff").unwrap(), [2..4, 5..6]);
+
+        // Classes are ASCII only and `.` matches any single byte
+        let regex = Regex::new(r"(?-u)\W.").unwrap();
+        assert_eq!(regex.find_bytes("aéb".as_bytes()).unwrap(), Some(1..3));
+        let regex = Regex::new(r"(?i-u)k").unwrap();
+        assert_eq!(regex.find_all_bytes("kK\u{212A}".as_bytes()).unwrap(), [0..1, 1..2]);
+
+        // Mixing byte and char matching falls back to the backtracker
+        let regex = Regex::new(r"(?-u:\xFF).").unwrap();
+        assert!(regex.program.is_none());
+        assert_eq!(regex.find_bytes(b"\xff\xff\xc3\xa9").unwrap(), Some(1..4));
+
+        // ... and still tries every byte offset
+        let regex = Regex::new(r"(?-u:[\xA9\xAA]).").unwrap();
+        assert!(regex.program.is_none());
+        assert_eq!(regex.find_bytes("éa".as_bytes()).unwrap(), Some(1..3));
+        let regex = Regex::new(r"(?-u)([\xA9\xAA])\1").unwrap();
+        assert_eq!(regex.find_bytes(b"\xc3\xa9\xa9").unwrap(), Some(1..3));
+        assert_eq!(regex.find_all_bytes(b"\xc3\xa9\xa9\xc3\xaa\xaa").unwrap(), [1..3, 4..6]);
+
+        let err = Regex::new("(?-u)[é]").unwrap_err();
+        assert_eq!(err.error, RegexError::NonByteClassMember { ch: 'é', span: Span::new(6, 7) });
+    }
+}
@@ -1,122 +1,119 @@
-use anyhow::*;
-use crate::matcher::{make_alpha_num_matcher, make_digit_matcher, Matcher};
+use crate::error::{RegexError, Span};
+use crate::matcher::{
+    make_alpha_num_matcher, make_digit_matcher, make_non_alpha_num_matcher, make_non_digit_matcher,
+    make_non_whitespace_matcher, make_whitespace_matcher, CharClass, Matcher, SetItem,
+};
 use crate::matcher::Matcher::Multiple;
 
+type Result<T> = std::result::Result<T, RegexError>;
+
+/// Largest repetition count, like `RE_DUP_MAX` in GNU grep.
+const MAX_REPETITION: usize = 32_767;
+
 #[derive(Debug)]
 pub struct RegexParser {
     pattern: Vec<char>,
     index: usize,
+    base: usize,
     next_group_idx: usize,
+    ignore_case: bool,
+    unicode: bool,
 }
 
 impl RegexParser {
     pub fn new(pattern: &str) -> RegexParser {
-        Self::new_with_next_group_idx(pattern, 1)
+        Self {
+            pattern: pattern.chars().collect(),
+            index: 0,
+            base: 0,
+            next_group_idx: 1,
+            ignore_case: false,
+            unicode: true,
+        }
+    }
+
+    /// Makes the whole pattern case-insensitive, as if it started with `(?i)`.
+    pub fn with_ignore_case(mut self, ignore_case: bool) -> RegexParser {
+        self.ignore_case = ignore_case;
+        self
     }
 
-    fn new_with_next_group_idx(pattern: &str, next_group_idx: usize) -> RegexParser {
+    /// Parser for a group segment starting at index `start` of this parser's
+    /// pattern. It continues the group numbering and reports spans for the
+    /// whole pattern.
+    fn new_segment_parser(&self, segment: &str, start: usize) -> RegexParser {
         Self {
-            pattern: pattern.chars().collect(),
+            pattern: segment.chars().collect(),
             index: 0,
-            next_group_idx,
+            base: self.base + start,
+            next_group_idx: self.next_group_idx,
+            ignore_case: self.ignore_case,
+            unicode: self.unicode,
         }
     }
 
     pub fn parse(&mut self) -> Result<Matcher> {
+        let (segments, _) = self.split_alternation(0, false)?;
+        if segments.len() > 1 {
+            let matchers = self.parse_segments(&segments)?;
+            self.index = self.pattern.len();
+            return Ok(Matcher::new_alternation(matchers));
+        }
+
         let mut matchers = vec![];
 
         while let Some(ch) = self.peek() {
             let matcher = match ch {
-                '\\' =>  {
-                    let next_ch = self
-                        .peek_nth(1)
-                        .ok_or(anyhow!("expected escaped char"))?;
-                    let matcher = match next_ch {
-                        'd' => make_digit_matcher(),
-                        'w' => make_alpha_num_matcher(),
-                        '\\' | '+' | '?' | '.' | '[' | '(' => Matcher::new_single_char(next_ch),
-                        _ => {
-                            if next_ch.is_ascii_digit() {
-                                let group_idx: usize = next_ch.to_digit(10).unwrap() as usize;
-                                if group_idx >= self.next_group_idx {
-                                    return Err(anyhow!("invalid group index"));
-                                }
-                                Matcher::new_group_reference(group_idx)
-                            } else {
-                                return Err(anyhow!("Invalid character '{}'", next_ch))
-                            }
-                        }
-                    };
-                    self.advance()?;
-                    self.advance()?;
-                    matcher
-                },
+                '\\' => self.parse_escape()?,
                 '[' => self.parse_group_matcher()?,
+                '(' if self.peek_nth(1) == Some('?') => match self.parse_flag_group()? {
+                    Some(matcher) => matcher,
+                    None => continue,
+                },
                 '(' => self.parse_group()?,
                 '^' => {
-                    self.advance()?;
+                    self.advance();
                     Matcher::new_start()
                 }
                 '$' => {
-                    self.advance()?;
+                    self.advance();
                     Matcher::new_end()
                 }
                 '+' => {
-                    self.advance()?;
-                    let last_matcher = matchers
-                        .iter()
-                        .last()
-                        .cloned()
-                        .ok_or(anyhow!("+ expects previous char"))?;
-                    let matcher = Matcher::new_one_or_more(
-                        Box::new(last_matcher), None);
-                    matchers.pop();
-                    matcher
+                    let last_matcher = self.pop_quantified(&mut matchers, ch)?;
+                    self.advance();
+                    Matcher::new_one_or_more(Box::new(last_matcher))
                 }
                 '*' => {
-                    self.advance()?;
-                    let last_matcher = matchers
-                        .iter()
-                        .last()
-                        .cloned()
-                        .ok_or(anyhow!("+ expects previous char"))?;
-                    let matcher = Matcher::new_zero_or_more(
-                        Box::new(last_matcher), None);
-                    matchers.pop();
-                    matcher
-                }                
+                    let last_matcher = self.pop_quantified(&mut matchers, ch)?;
+                    self.advance();
+                    Matcher::new_zero_or_more(Box::new(last_matcher))
+                }
                 '?' => {
-                    self.advance()?;
-                    let last_matcher = matchers
-                        .iter()
-                        .last()
-                        .ok_or(anyhow!("+ expects previous char"))?;
-                    let matcher = Matcher::new_zero_or_one(last_matcher);
-                    matchers.pop();
-                    matcher
+                    let last_matcher = self.pop_quantified(&mut matchers, ch)?;
+                    self.advance();
+                    Matcher::new_zero_or_one(&last_matcher)
                 }
                 '{' => {
-                    let last_matcher = matchers
-                        .iter()
-                        .last()
-                        .ok_or(anyhow!("+ expects previous char"))?;
+                    let last_matcher = self.pop_quantified(&mut matchers, ch)?;
                     let (min, max) = self.parse_quantifiers()?;
-                    let matcher = Multiple {
-                        matcher: Box::new(last_matcher.clone()),
+                    Multiple {
+                        matcher: Box::new(last_matcher),
                         min,
                         max,
-                        follow: None
-                    };
-                    matchers.pop();
-                    matcher
+                    }
                 }
-                '.' => {
-                    self.advance()?;
+                '.' if self.unicode => {
+                    self.advance();
                     Matcher::new_wildcard()
                 }
+                '.' => {
+                    self.advance();
+                    Matcher::new_any_byte()
+                }
                 _ => {
-                    self.advance()?;
-                    Matcher::new_single_char(ch)
+                    self.advance();
+                    self.make_literal(ch)
                 },
             };
 
@@ -124,151 +121,478 @@ impl RegexParser {
         }
 
         match matchers.len() {
-            0 => Err(anyhow!("No matcher found")),
+            // An empty pattern matches every line, as in grep
+            0 => Ok(Matcher::new_sequence(matchers)),
             1 => Ok(matchers[0].clone()),
             _ => Ok(Matcher::new_sequence(matchers)),
         }
     }
 
-    fn parse_quantifiers(&mut self) -> Result<(usize, Option<usize>)> {
-        self.advance()?;
-        let mut min_str = String::new();
-        loop {
-            let ch = self.advance()?;
-            if ch.is_ascii_digit() {
-                min_str.push(ch);
-            } else if ch == ',' {
-                break;
-            } else if ch == '}' {
-                let min = min_str.parse::<usize>()?;
-                return Ok((min, Some(min)));
-            } else {
-                return Err(anyhow!("invalid quantifier character '{}'", ch));
+    /// Parses an escape sequence outside of brackets: a shorthand class, an
+    /// assertion, a backreference or an escaped char.
+    fn parse_escape(&mut self) -> Result<Matcher> {
+        let start = self.index;
+        self.advance();
+        let escaped = self
+            .advance()
+            .ok_or(RegexError::TrailingBackslash { span: self.span_from(start) })?;
+
+        let matcher = match escaped {
+            'd' => self.class_matcher(make_digit_matcher()),
+            'D' => self.class_matcher(make_non_digit_matcher()),
+            'w' => self.class_matcher(make_alpha_num_matcher()),
+            'W' => self.class_matcher(make_non_alpha_num_matcher()),
+            's' => self.class_matcher(make_whitespace_matcher()),
+            'S' => self.class_matcher(make_non_whitespace_matcher()),
+            'b' => Matcher::new_word_boundary(self.unicode),
+            'B' => Matcher::new_non_word_boundary(self.unicode),
+            '<' => Matcher::new_word_start(self.unicode),
+            '>' => Matcher::new_word_end(self.unicode),
+            // Lines are matched one at a time, so the start and end of the
+            // input are the same as `^` and `$`
+            'A' => Matcher::new_start(),
+            'z' => Matcher::new_end(),
+            '1'..='9' => {
+                let group_idx = escaped.to_digit(10).unwrap() as usize;
+                if group_idx >= self.next_group_idx {
+                    return Err(RegexError::BadBackreference {
+                        group_idx,
+                        span: self.span_from(start),
+                    });
+                }
+                Matcher::new_group_reference(group_idx, self.ignore_case)
             }
+            // In byte mode `\xHH` is the byte HH, not the char U+00HH
+            'x' if !self.unicode => {
+                let ch = self.parse_escaped_char(escaped, start)?;
+                match u8::try_from(ch) {
+                    Ok(byte) if !byte.is_ascii() => Matcher::new_byte(byte),
+                    _ => self.make_literal(ch),
+                }
+            }
+            _ => {
+                let ch = self.parse_escaped_char(escaped, start)?;
+                self.make_literal(ch)
+            }
+        };
+        Ok(matcher)
+    }
+
+    /// Resolves the escape `\escaped` (the backslash is at `start`) to the
+    /// char it stands for. Any non-alphanumeric char escapes to itself.
+    fn parse_escaped_char(&mut self, escaped: char, start: usize) -> Result<char> {
+        match escaped {
+            't' => Ok('\t'),
+            'n' => Ok('\n'),
+            'r' => Ok('\r'),
+            'f' => Ok('\x0C'),
+            'v' => Ok('\x0B'),
+            'x' => {
+                let digits: String = (0..2).filter_map(|_| self.advance()).collect();
+                if digits.len() != 2 {
+                    return Err(RegexError::BadHexEscape { span: self.span_from(start) });
+                }
+                self.parse_code_point(&digits, start)
+            }
+            'u' => {
+                if self.advance() != Some('{') {
+                    return Err(RegexError::BadHexEscape { span: self.span_from(start) });
+                }
+                let mut digits = String::new();
+                loop {
+                    match self.advance() {
+                        Some('}') => break,
+                        Some(ch) => digits.push(ch),
+                        None => return Err(RegexError::BadHexEscape { span: self.span_from(start) }),
+                    }
+                }
+                self.parse_code_point(&digits, start)
+            }
+            _ if !escaped.is_alphanumeric() => Ok(escaped),
+            _ => Err(RegexError::BadEscape {
+                escape: escaped,
+                span: self.span_from(start),
+            }),
         }
+    }
 
-        let mut max_str = String::new();
-        loop {
-            let ch = self.advance()?;
-            if ch.is_ascii_digit() {
-                max_str.push(ch);
-            } else if ch == '}' {
-                break;
-            } else {
-                return Err(anyhow!("invalid quantifier character '{}'", ch));
+    fn parse_code_point(&self, digits: &str, start: usize) -> Result<char> {
+        let valid_len = (1..=6).contains(&digits.len());
+        let code_point = digits
+            .chars()
+            .all(|ch| ch.is_ascii_hexdigit())
+            .then(|| u32::from_str_radix(digits, 16).ok())
+            .flatten();
+
+        match code_point.and_then(char::from_u32) {
+            Some(ch) if valid_len => Ok(ch),
+            _ => Err(RegexError::BadHexEscape { span: self.span_from(start) }),
+        }
+    }
+
+    /// Removes the matcher a quantifier applies to.
+    fn pop_quantified(&self, matchers: &mut Vec<Matcher>, quantifier: char) -> Result<Matcher> {
+        matchers.pop().ok_or(RegexError::DanglingQuantifier {
+            quantifier,
+            span: self.span_at(0),
+        })
+    }
+
+    fn parse_quantifiers(&mut self) -> Result<(usize, Option<usize>)> {
+        let start = self.index;
+        self.advance();
+        let min_str = self.take_digits();
+        let min = self.parse_count(&min_str, start)?;
+
+        let max = match self.advance() {
+            Some('}') => return Ok((min, Some(min))),
+            Some(',') => {
+                let max_str = self.take_digits();
+                match self.advance() {
+                    Some('}') if max_str.is_empty() => None,
+                    Some('}') => Some(self.parse_count(&max_str, start)?),
+                    Some(_) => return Err(RegexError::BadRepetition { span: self.span_from(start) }),
+                    None => return Err(RegexError::UnclosedRepetition { span: self.span_from(start) }),
+                }
             }
+            Some(_) => return Err(RegexError::BadRepetition { span: self.span_from(start) }),
+            None => return Err(RegexError::UnclosedRepetition { span: self.span_from(start) }),
+        };
+
+        match max {
+            Some(max) if max < min => Err(RegexError::BadRange {
+                min,
+                max,
+                span: self.span_from(start),
+            }),
+            _ => Ok((min, max)),
         }
+    }
 
-        if max_str.is_empty() {
-            let min = min_str.parse::<usize>()?;
-            Ok((min, None))
+    fn take_digits(&mut self) -> String {
+        let mut digits = String::new();
+        while let Some(ch) = self.peek().filter(char::is_ascii_digit) {
+            digits.push(ch);
+            self.advance();
+        }
+        digits
+    }
+
+    fn parse_count(&self, digits: &str, start: usize) -> Result<usize> {
+        digits
+            .parse::<usize>()
+            .ok()
+            .filter(|count| *count <= MAX_REPETITION)
+            .ok_or_else(|| RegexError::BadRepetition { span: self.span_from(start) })
+    }
+
+    fn make_literal(&self, ch: char) -> Matcher {
+        if !self.unicode && !ch.is_ascii() {
+            // Byte mode matches chars by their UTF-8 encoding
+            let mut buffer = [0; 4];
+            let bytes = ch.encode_utf8(&mut buffer).bytes().map(Matcher::new_byte).collect();
+            return Matcher::new_sequence(bytes);
+        }
+        if !self.unicode && self.ignore_case && ch.is_ascii_alphabetic() {
+            return Matcher::new_byte_branch(vec![SetItem::Char(ch)], false, true);
+        }
+        if self.ignore_case {
+            Matcher::new_single_char_ignore_case(ch)
         } else {
-            let min = min_str.parse::<usize>()?;
-            let max = max_str.parse::<usize>()?;
-            if max < min {
-                return Err(anyhow!("max quantifier must be >= min quantifier"));
-            }
-            Ok((min, Some(max)))
+            Matcher::new_single_char(ch)
+        }
+    }
+
+    /// Turns a shorthand class like `\d` into a byte set in byte mode.
+    fn class_matcher(&self, matcher: Matcher) -> Matcher {
+        match matcher {
+            Matcher::SingleCharBranch(char_set) if !self.unicode =>
+                Matcher::new_byte_branch(char_set.items, char_set.negated, char_set.ignore_case),
+            matcher => matcher,
         }
     }
 
     fn parse_group(&mut self) -> Result<Matcher> {
-        let (segments, consumed_len) = self.split_alternation()?;
-        let mut matchers = vec![];
+        let (segments, consumed_len) = self.split_alternation(1, true)?;
         let group_idx = self.next_group_idx;
         self.next_group_idx += 1;
 
-        for segment in &segments {
-            let mut parser = RegexParser::new_with_next_group_idx(segment, self.next_group_idx);
-            let matcher = parser.parse()?;
-            matchers.push(matcher);
-            self.next_group_idx = parser.next_group_idx;
-        }
+        let matchers = self.parse_segments(&segments)?;
         self.index += consumed_len;
 
         Ok(Matcher::new_group(matchers, group_idx))
     }
 
-    fn split_alternation(&self) -> Result<(Vec<String>, usize)> {
+    /// Parses `(?flags)`, which sets flags for the rest of the enclosing group
+    /// (or pattern), later alternatives included, or `(?flags:...)`, a
+    /// non-capturing group with its own flags. A `-` turns off the flags
+    /// after it, as in `(?-i)`.
+    ///
+    /// The flags are `i` for case-insensitive matching and `u` for Unicode
+    /// mode, which is on by default. With `(?-u)` the pattern matches bytes:
+    /// `.` and classes match a single byte, `\xHH` stands for a byte and case
+    /// folding is limited to ASCII.
+    fn parse_flag_group(&mut self) -> Result<Option<Matcher>> {
+        let mut ignore_case = self.ignore_case;
+        let mut unicode = self.unicode;
+        let mut enable = true;
+        let mut prefix_len = 2;
+
+        loop {
+            match self.peek_nth(prefix_len) {
+                Some('i') => ignore_case = enable,
+                Some('u') => unicode = enable,
+                Some('-') if enable => enable = false,
+                Some(')') => {
+                    self.index += prefix_len + 1;
+                    self.ignore_case = ignore_case;
+                    self.unicode = unicode;
+                    return Ok(None);
+                }
+                Some(':') => break,
+                Some(flag) => return Err(RegexError::BadFlag { flag, span: self.span_at(prefix_len) }),
+                None => return Err(RegexError::UnclosedParen { span: self.span_at(0) }),
+            }
+            prefix_len += 1;
+        }
+
+        let (segments, consumed_len) = self.split_alternation(prefix_len + 1, true)?;
+        let outer_flags = (self.ignore_case, self.unicode);
+        (self.ignore_case, self.unicode) = (ignore_case, unicode);
+        let matchers = self.parse_segments(&segments);
+        (self.ignore_case, self.unicode) = outer_flags;
+        self.index += consumed_len;
+
+        Ok(Some(Matcher::new_alternation(matchers?)))
+    }
+
+    fn parse_segments(&mut self, segments: &[(String, usize)]) -> Result<Vec<Matcher>> {
+        let outer_flags = (self.ignore_case, self.unicode);
+        let mut matchers = vec![];
+        for (segment, segment_start) in segments {
+            let mut parser = self.new_segment_parser(segment, *segment_start);
+            matchers.push(parser.parse()?);
+            self.next_group_idx = parser.next_group_idx;
+            // Flags set by `(?flags)` carry over into the later alternatives
+            (self.ignore_case, self.unicode) = (parser.ignore_case, parser.unicode);
+        }
+        (self.ignore_case, self.unicode) = outer_flags;
+        Ok(matchers)
+    }
+
+    /// Splits the group starting at the current index into its alternatives.
+    /// The alternatives start after the `prefix_len` chars that open the
+    /// group. Each segment is returned together with its index in the pattern.
+    ///
+    /// Outside of a group, the rest of the pattern is split at its top-level
+    /// `|` instead. A `)` without a matching `(` is a literal there.
+    fn split_alternation(&self, prefix_len: usize, in_group: bool) -> Result<(Vec<(String, usize)>, usize)> {
         let mut segments = vec![];
         let mut segment = String::new();
-        let mut level = 0;
+        let mut segment_start = self.index + prefix_len;
+        let outer_level = usize::from(in_group);
+        let mut level = outer_level;
         let mut consumed_len = 0;
 
-        for (idx, ch) in self.pattern[self.index..].iter().enumerate() {
+        let mut idx = prefix_len;
+        while let Some(ch) = self.pattern.get(self.index + idx) {
             match *ch {
-                '(' => {
-                    level += 1;
-                    if level == 1 {
-                        continue;
-                    }
-                },
+                '(' => level += 1,
+                ')' if level == 0 => {}
                 ')' => {
                     level -= 1;
-                    if level == 0 {
+                    if level == 0 && in_group {
                         if segment.is_empty() {
-                            return Err(anyhow!("Empty alternation"));
+                            return Err(RegexError::EmptyAlternative { span: self.span_at(idx) });
                         }
-                        segments.push(segment.clone());
+                        segments.push((segment.clone(), segment_start));
                         segment.clear();
                         consumed_len = idx + 1;
                         break;
                     }
                 },
-                '|' => {
-                    if level == 1 {
-                        if segment.is_empty() {
-                            return Err(anyhow!("Empty alternation"));
-                        }
-                        segments.push(segment.clone());
-                        segment.clear();
-                        continue;
+                '|' if level == outer_level => {
+                    if segment.is_empty() {
+                        return Err(RegexError::EmptyAlternative { span: self.span_at(idx) });
                     }
+                    segments.push((segment.clone(), segment_start));
+                    segment.clear();
+                    idx += 1;
+                    segment_start = self.index + idx;
+                    continue;
                 }
                 _ => {}
             }
-            segment.push(*ch);
+
+            // Escaped chars and bracket expressions never open or close groups
+            let len = match *ch {
+                '\\' => 2,
+                '[' => self.bracket_len(self.index + idx).unwrap_or(1),
+                _ => 1,
+            };
+            let end = (self.index + idx + len).min(self.pattern.len());
+            segment.extend(&self.pattern[self.index + idx..end]);
+            idx += len;
         }
 
-        if !segment.is_empty() || level != 0 {
-            return Err(anyhow!("Invalid alternation pattern"));
+        if !in_group {
+            // Unclosed groups are reported when they are parsed
+            if segment.is_empty() && !segments.is_empty() {
+                return Err(RegexError::EmptyAlternative { span: self.span_at(idx - 1) });
+            }
+            segments.push((segment, segment_start));
+            return Ok((segments, idx));
+        }
+        if level != 0 {
+            return Err(RegexError::UnclosedParen { span: self.span_at(0) });
         }
 
         Ok((segments, consumed_len))
     }
 
+    /// Parses a bracket expression such as `[^a-z\d_]` or `[[:alpha:]_]`.
+    ///
+    /// As in POSIX, a `]` right after the opening `[` or `[^` and a `-` at the
+    /// start or end are literal members.
     fn parse_group_matcher(&mut self) -> Result<Matcher> {
-        let mut characters = vec![];
-        let mut is_negated = false;
+        let mut items = vec![];
+        let start = self.index;
 
-        self.advance()?;
-        let ch = self.peek().ok_or(anyhow!("expected character"))?;
-        if ch == '^' {
-            is_negated = true;
-        } else {
-            characters.push(ch);
+        self.advance();
+        let is_negated = self.peek() == Some('^');
+        if is_negated {
+            self.advance();
         }
-        self.advance()?;
 
+        let mut first = true;
         loop {
-            let ch = self.advance()?;
-            if ch == ']' {
+            let ch = self.peek().ok_or(RegexError::UnclosedBracket { span: self.span_from(start) })?;
+            if ch == ']' && !first {
+                self.advance();
                 break;
             }
-            characters.push(ch);
+            first = false;
+
+            let item_start = self.index;
+            let item = self.parse_set_member(start)?;
+            let is_range = self.peek() == Some('-') && self.peek_nth(1).is_some_and(|c| c != ']');
+
+            match item {
+                SetItem::Char(from) if is_range => {
+                    self.advance();
+                    match self.parse_set_member(start)? {
+                        SetItem::Char(to) if from <= to => items.push(SetItem::Range(from, to)),
+                        _ => return Err(RegexError::BadCharRange { span: self.span_from(item_start) }),
+                    }
+                }
+                _ => items.push(item),
+            }
         }
 
-        Ok(Matcher::new_single_char_branch(characters, is_negated))
+        if self.unicode {
+            Ok(Matcher::new_single_char_branch(items, is_negated, self.ignore_case))
+        } else {
+            Ok(Matcher::new_byte_branch(items, is_negated, self.ignore_case))
+        }
     }
 
-    fn advance(&mut self) -> Result<char> {
-        if self.index >= self.pattern.len() {
-            return Err(anyhow!("End of pattern reached"));
+    /// Parses one member of a bracket expression starting at `[` index `start`.
+    fn parse_set_member(&mut self, start: usize) -> Result<SetItem> {
+        let unclosed = |parser: &Self| RegexError::UnclosedBracket { span: parser.span_from(start) };
+
+        if let Some(len) = self.posix_class_len(self.index) {
+            let name: String = self.pattern[self.index + 2..self.index + len - 2].iter().collect();
+            let span = self.span_between(0, len);
+            self.index += len;
+            return CharClass::from_posix_name(&name)
+                .map(SetItem::Class)
+                .ok_or(RegexError::BadCharClass { name, span });
         }
-        let ch = self.pattern[self.index];
-        self.index += 1;
 
-        Ok(ch)
+        let ch = self.advance().ok_or_else(|| unclosed(self))?;
+        if ch != '\\' {
+            return self.make_set_char(ch, self.index - 1);
+        }
+
+        let escape_start = self.index - 1;
+        let escaped = self.advance().ok_or_else(|| unclosed(self))?;
+        match escaped {
+            'd' => Ok(SetItem::Class(CharClass::Digit)),
+            'D' => Ok(SetItem::NegatedClass(CharClass::Digit)),
+            'w' => Ok(SetItem::Class(CharClass::Word)),
+            'W' => Ok(SetItem::NegatedClass(CharClass::Word)),
+            's' => Ok(SetItem::Class(CharClass::Space)),
+            'S' => Ok(SetItem::NegatedClass(CharClass::Space)),
+            // Up to U+00FF, which stands for the byte in byte mode
+            'x' => Ok(SetItem::Char(self.parse_escaped_char(escaped, escape_start)?)),
+            _ => {
+                let ch = self.parse_escaped_char(escaped, escape_start)?;
+                self.make_set_char(ch, escape_start)
+            }
+        }
+    }
+
+    /// A char member starting at `start`. Members of byte mode sets must be
+    /// single bytes, so other non-ASCII chars are rejected there.
+    fn make_set_char(&self, ch: char, start: usize) -> Result<SetItem> {
+        if !self.unicode && !ch.is_ascii() {
+            return Err(RegexError::NonByteClassMember { ch, span: self.span_from(start) });
+        }
+        Ok(SetItem::Char(ch))
+    }
+
+    /// Length of the bracket expression starting at `start`, including the
+    /// closing `]`, or `None` if it is not terminated.
+    fn bracket_len(&self, start: usize) -> Option<usize> {
+        let mut idx = start + 1;
+        if self.pattern.get(idx) == Some(&'^') {
+            idx += 1;
+        }
+        if self.pattern.get(idx) == Some(&']') {
+            idx += 1;
+        }
+        while let Some(ch) = self.pattern.get(idx) {
+            match ch {
+                '\\' => idx += 2,
+                ']' => return Some(idx + 1 - start),
+                _ => idx += self.posix_class_len(idx).unwrap_or(1),
+            }
+        }
+        None
+    }
+
+    /// Length of a `[:name:]` class starting at `start`, if there is one.
+    fn posix_class_len(&self, start: usize) -> Option<usize> {
+        if self.pattern.get(start) != Some(&'[') || self.pattern.get(start + 1) != Some(&':') {
+            return None;
+        }
+        let rest = &self.pattern[start + 2..];
+        let close = rest.windows(2).position(|w| w == [':', ']'])?;
+        if rest[..close].contains(&']') {
+            return None;
+        }
+        Some(close + 4)
+    }
+
+    /// Span of the char `n` positions after the current one.
+    fn span_at(&self, n: usize) -> Span {
+        Span::at(self.base + self.index + n)
+    }
+
+    /// Span from `n` to `m` chars after the current position.
+    fn span_between(&self, n: usize, m: usize) -> Span {
+        Span::new(self.base + self.index + n, self.base + self.index + m)
+    }
+
+    /// Span from the local index `start` up to the current position.
+    fn span_from(&self, start: usize) -> Span {
+        Span::new(self.base + start, self.base + self.index)
+    }
+
+    fn advance(&mut self) -> Option<char> {
+        let ch = self.peek()?;
+        self.index += 1;
+        Some(ch)
     }
 
     fn peek(&self) -> Option<char> {
@@ -289,11 +613,18 @@ impl RegexParser {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::error::{MatchError, Span};
+    use crate::input::Input;
+    use crate::matcher::fold_case;
 
     fn make_matcher(pattern: &str) -> Matcher {
         RegexParser::new(pattern).parse().unwrap()
     }
 
+    fn parse_err(pattern: &str) -> RegexError {
+        RegexParser::new(pattern).parse().unwrap_err()
+    }
+
     #[test]
     fn test_single_char_matcher() {
         let matcher = make_matcher("a");
@@ -490,4 +821,463 @@ mod tests {
         assert_eq!(matches.len(), 6);
 
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_backtracking_over_wildcards() {
+        let matcher = make_matcher("a.*b.*c");
+        let m = matcher.find_match("xaxxbxxcxbx");
+        assert!(m.is_some());
+        assert_eq!(m.unwrap().matched_text, "axxbxxc");
+        assert!(matcher.find_match("acb").is_none());
+    }
+
+    #[test]
+    fn test_backtracking_quantifier_count() {
+        let matcher = make_matcher(r"\d+\d");
+        let m = matcher.find_match("abc 1234");
+        assert!(m.is_some());
+        assert_eq!(m.unwrap().matched_text, "1234");
+        assert!(matcher.find_match("abc 1").is_none());
+    }
+
+    #[test]
+    fn test_backtracking_into_alternation() {
+        let matcher = make_matcher("(a|ab)c");
+        let m = matcher.find_match("abc");
+        assert!(m.is_some());
+        assert_eq!(m.unwrap().matched_text, "abc");
+    }
+
+    #[test]
+    fn test_empty_repetition_terminates() {
+        let matcher = make_matcher("(a*)*b");
+        assert!(matcher.find_match("aaab").is_some());
+        assert!(matcher.find_match("aaa").is_none());
+
+        let matches = make_matcher("x*").find_all_matches("ab");
+        assert_eq!(matches.len(), 3);
+    }
+
+    #[test]
+    fn test_empty_iteration_sets_group() {
+        let m = make_matcher(r"(b*)*c\1").find_match("c").unwrap();
+        assert_eq!(m.matched_text, "c");
+        assert_eq!(m.sub_matches[&1].matched_text, "");
+
+        let m = make_matcher(r"(x?)*y\1z").find_match("yz").unwrap();
+        assert_eq!(m.matched_text, "yz");
+
+        let m = make_matcher("(a*)*b").find_match("aaab").unwrap();
+        assert_eq!(m.sub_matches[&1].matched_text, "");
+    }
+
+    #[test]
+    fn test_alternation_backtracks_into_later_branch() {
+        let matcher = make_matcher("(cat|category) list");
+        let m = matcher.find_match("category list");
+        assert!(m.is_some());
+        let m = m.unwrap();
+        assert_eq!(m.matched_text, "category list");
+        assert_eq!(m.sub_matches[&1].matched_text, "category");
+    }
+
+    #[test]
+    fn test_alternation_backtracking_restores_sub_matches() {
+        let matcher = make_matcher(r"(a|ab)(c|bcd)(d*)");
+        let m = matcher.find_match("abcd").unwrap();
+        assert_eq!(m.matched_text, "abcd");
+        assert_eq!(m.sub_matches[&1].matched_text, "a");
+        assert_eq!(m.sub_matches[&2].matched_text, "bcd");
+        assert_eq!(m.sub_matches[&3].matched_text, "");
+    }
+
+    #[test]
+    fn test_backreference_sees_backtracked_alternative() {
+        let matcher = make_matcher(r"(a|ab)x\1");
+        let m = matcher.find_match("abxab");
+        assert!(m.is_some());
+        assert_eq!(m.unwrap().sub_matches[&1].matched_text, "ab");
+
+        let matcher = make_matcher(r"((x)|(y))+-\2\3");
+        let m = matcher.find_match("xy-xy").unwrap();
+        assert_eq!(m.sub_matches[&1].matched_text, "y");
+        assert_eq!(m.sub_matches[&2].matched_text, "x");
+        assert_eq!(m.sub_matches[&3].matched_text, "y");
+    }
+
+    #[test]
+    fn test_top_level_alternation() {
+        let matcher = make_matcher("a|b");
+        assert_eq!(matcher.find_match("xb").unwrap().matched_text, "b");
+        assert!(matcher.find_match("c").is_none());
+
+        let matcher = make_matcher("^ab|cd$");
+        assert!(matcher.find_match("abx").is_some());
+        assert!(matcher.find_match("xcd").is_some());
+        assert!(matcher.find_match("xab cdx").is_none());
+
+        // Groups are numbered across the alternatives
+        let m = make_matcher(r"(a)x|(b)\2").find_match("abb").unwrap();
+        assert_eq!(m.matched_text, "bb");
+        assert_eq!(m.sub_matches[&2].matched_text, "b");
+
+        // A `)` without `(` stays a literal
+        assert!(make_matcher("a)|b").find_match("a)").is_some());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(parse_err("a(*b)"), RegexError::DanglingQuantifier { quantifier: '*', span: Span::new(2, 3) });
+        assert_eq!(parse_err("?"), RegexError::DanglingQuantifier { quantifier: '?', span: Span::new(0, 1) });
+        assert_eq!(parse_err("ab(c"), RegexError::UnclosedParen { span: Span::new(2, 3) });
+        assert_eq!(parse_err("ab\\"), RegexError::TrailingBackslash { span: Span::new(2, 3) });
+        assert_eq!(parse_err("(a)\\2"), RegexError::BadBackreference { group_idx: 2, span: Span::new(3, 5) });
+        assert_eq!(parse_err("a{2,1}"), RegexError::BadRange { min: 2, max: 1, span: Span::new(1, 6) });
+        assert_eq!(parse_err("a{x}"), RegexError::BadRepetition { span: Span::new(1, 2) });
+        assert_eq!(parse_err("a{32768}"), RegexError::BadRepetition { span: Span::new(1, 7) });
+        assert_eq!(parse_err("a{1,18446744073709551615}a"), RegexError::BadRepetition { span: Span::new(1, 25) });
+        assert_eq!(parse_err("a{2"), RegexError::UnclosedRepetition { span: Span::new(1, 3) });
+        assert_eq!(parse_err("(x|[ab"), RegexError::UnclosedParen { span: Span::new(0, 1) });
+        assert_eq!(parse_err("a|"), RegexError::EmptyAlternative { span: Span::new(1, 2) });
+        assert_eq!(parse_err("|a"), RegexError::EmptyAlternative { span: Span::new(0, 1) });
+        assert_eq!(parse_err("a|(b"), RegexError::UnclosedParen { span: Span::new(2, 3) });
+        assert_eq!(parse_err("x[ab"), RegexError::UnclosedBracket { span: Span::new(1, 4) });
+    }
+
+    #[test]
+    fn test_bracket_ranges() {
+        let matcher = make_matcher("[a-z0-9]+");
+        assert_eq!(matcher.find_match("ABC xy42!").unwrap().matched_text, "xy42");
+
+        let matcher = make_matcher("[^a-f]");
+        assert_eq!(matcher.find_match("abcdefg").unwrap().matched_text, "g");
+    }
+
+    #[test]
+    fn test_bracket_escapes_and_classes() {
+        let matcher = make_matcher(r"[\]\\\-]+");
+        assert_eq!(matcher.find_match("ab]\\-]c").unwrap().matched_text, "]\\-]");
+
+        let matcher = make_matcher(r"[\d\s]+");
+        assert_eq!(matcher.find_match("ab1 2\t3x").unwrap().matched_text, "1 2\t3");
+
+        let matcher = make_matcher(r"[^\w]");
+        assert_eq!(matcher.find_match("ab_1-").unwrap().matched_text, "-");
+    }
+
+    #[test]
+    fn test_bracket_posix_literal_members() {
+        let matcher = make_matcher("[]a]+");
+        assert_eq!(matcher.find_match("x]a]y").unwrap().matched_text, "]a]");
+
+        let matcher = make_matcher("[^]]+");
+        assert_eq!(matcher.find_match("]]ab]").unwrap().matched_text, "ab");
+
+        let matcher = make_matcher("[-a][a-]");
+        assert!(matcher.find_match("-a").is_some());
+        assert!(matcher.find_match("a-").is_some());
+    }
+
+    #[test]
+    fn test_brackets_inside_groups() {
+        let matcher = make_matcher(r"(x|[)|(])+y");
+        assert_eq!(matcher.find_match("a)(|xy").unwrap().matched_text, ")(|xy");
+    }
+
+    #[test]
+    fn test_bracket_errors() {
+        assert_eq!(parse_err("[z-a]"), RegexError::BadCharRange { span: Span::new(1, 4) });
+        assert_eq!(parse_err(r"[a-\d]"), RegexError::BadCharRange { span: Span::new(1, 5) });
+        assert_eq!(parse_err("[]"), RegexError::UnclosedBracket { span: Span::new(0, 2) });
+        assert_eq!(parse_err(r"[\q]"), RegexError::BadEscape { escape: 'q', span: Span::new(1, 3) });
+    }
+
+    #[test]
+    fn test_posix_classes() {
+        let matcher = make_matcher("[[:alpha:]]+");
+        assert_eq!(matcher.find_match("12 Grüße 3").unwrap().matched_text, "Grüße");
+
+        let matcher = make_matcher("[[:space:]]+");
+        assert_eq!(matcher.find_match("a \t b").unwrap().matched_text, " \t ");
+
+        let matcher = make_matcher("[[:punct:]]+");
+        assert_eq!(matcher.find_match("abc!?.def").unwrap().matched_text, "!?.");
+
+        let matcher = make_matcher("[[:upper:][:digit:]_]+");
+        assert_eq!(matcher.find_match("abcAB_12cd").unwrap().matched_text, "AB_12");
+
+        let matcher = make_matcher("[^[:alnum:][:blank:]]");
+        assert_eq!(matcher.find_match("ab 12\t-").unwrap().matched_text, "-");
+
+        let matcher = make_matcher("[[:xdigit:]]+");
+        assert_eq!(matcher.find_match("xyz0fA9g").unwrap().matched_text, "0fA9");
+    }
+
+    #[test]
+    fn test_all_posix_class_names() {
+        for name in ["alnum", "alpha", "blank", "cntrl", "digit", "graph",
+                     "lower", "print", "punct", "space", "upper", "xdigit"] {
+            assert!(RegexParser::new(&format!("[[:{name}:]]")).parse().is_ok(), "{name}");
+        }
+    }
+
+    #[test]
+    fn test_posix_class_errors() {
+        assert_eq!(
+            parse_err("a[[:foo:]]"),
+            RegexError::BadCharClass { name: "foo".to_string(), span: Span::new(2, 9) }
+        );
+        // Without a closing ":]" the '[' is an ordinary member
+        assert!(make_matcher("[[:]").find_match(":").is_some());
+    }
+
+    #[test]
+    fn test_posix_class_inside_group() {
+        let matcher = make_matcher("(x|[[:digit:]])+");
+        assert_eq!(matcher.find_match("ab1x2c").unwrap().matched_text, "1x2");
+    }
+
+    #[test]
+    fn test_negated_shorthands() {
+        let matcher = make_matcher(r"\D+");
+        assert_eq!(matcher.find_match("12ab3").unwrap().matched_text, "ab");
+
+        let matcher = make_matcher(r"\W+");
+        assert_eq!(matcher.find_match("ab, cd").unwrap().matched_text, ", ");
+
+        let matcher = make_matcher(r"\S+\s+\S+");
+        assert_eq!(matcher.find_match("  foo \t bar ").unwrap().matched_text, "foo \t bar");
+
+        let matcher = make_matcher(r"[\D\s]+");
+        assert_eq!(matcher.find_match("12a b3").unwrap().matched_text, "a b");
+    }
+
+    #[test]
+    fn test_control_and_hex_escapes() {
+        let matcher = make_matcher(r"a\tb");
+        assert!(matcher.find_match("a\tb").is_some());
+
+        let matcher = make_matcher(r"\x41\u{e4}\u{1F600}");
+        assert!(matcher.find_match("xAä😀").is_some());
+
+        let matcher = make_matcher(r"[\x41-\x43]+");
+        assert_eq!(matcher.find_match("xABCD").unwrap().matched_text, "ABC");
+    }
+
+    #[test]
+    fn test_escaped_metacharacters() {
+        let matcher = make_matcher(r"\(\*\{\|\}\)\^\$\]\-");
+        assert!(matcher.find_match("x(*{|})^$]-y").is_some());
+
+        let matcher = make_matcher(r"(\)|\()+");
+        assert_eq!(matcher.find_match("a)(b").unwrap().matched_text, ")(");
+    }
+
+    #[test]
+    fn test_escape_errors() {
+        assert_eq!(parse_err(r"a\q"), RegexError::BadEscape { escape: 'q', span: Span::new(1, 3) });
+        assert_eq!(parse_err(r"\xZZ"), RegexError::BadHexEscape { span: Span::new(0, 4) });
+        assert_eq!(parse_err(r"\x4"), RegexError::BadHexEscape { span: Span::new(0, 3) });
+        assert_eq!(parse_err(r"\u{110000}"), RegexError::BadHexEscape { span: Span::new(0, 10) });
+        assert_eq!(parse_err(r"\u{41"), RegexError::BadHexEscape { span: Span::new(0, 5) });
+    }
+
+    #[test]
+    fn test_word_boundary() {
+        let matcher = make_matcher(r"\bid\b");
+        assert!(matcher.find_match("user id = 3").is_some());
+        assert!(matcher.find_match("id").is_some());
+        assert!(matcher.find_match("user_id = 3").is_none());
+        assert!(matcher.find_match("identity").is_none());
+
+        let matches = make_matcher(r"\b\w+\b").find_all_matches("ab, cd!");
+        let words: Vec<_> = matches.iter().map(|m| m.matched_text.as_str()).collect();
+        assert_eq!(words, ["ab", "cd"]);
+    }
+
+    #[test]
+    fn test_unicode_word_chars() {
+        let matches = make_matcher(r"\w+").find_all_matches("Grüße, Straße_3!");
+        let words: Vec<_> = matches.iter().map(|m| m.matched_text.as_str()).collect();
+        assert_eq!(words, ["Grüße", "Straße_3"]);
+        assert!(make_matcher(r"\<über\>").find_match("über alles").is_some());
+        assert!(make_matcher(r"\bber").find_match("über").is_none());
+
+        // Only ASCII chars are word chars in byte mode
+        assert!(make_matcher(r"(?-u)\bber").find_match("über").is_some());
+        assert_eq!(make_matcher(r"(?-u)\w+").find_match("Grüße").unwrap().matched_text, "Gr");
+    }
+
+    #[test]
+    fn test_non_word_boundary() {
+        let matcher = make_matcher(r"\Bcat\B");
+        assert!(matcher.find_match("concatenate").is_some());
+        assert!(matcher.find_match("cat food").is_none());
+    }
+
+    #[test]
+    fn test_word_start_and_end() {
+        let matcher = make_matcher(r"\<for\>");
+        assert!(matcher.find_match("a for loop").is_some());
+        assert!(matcher.find_match("before").is_none());
+        assert!(matcher.find_match("forward").is_none());
+
+        let matcher = make_matcher(r"\<\w");
+        assert_eq!(matcher.find_match("  xy").unwrap().offset, 2);
+        let matcher = make_matcher(r"\w\>");
+        assert_eq!(matcher.find_match("xy  ").unwrap().offset, 1);
+    }
+
+    #[test]
+    fn test_input_anchors() {
+        let matcher = make_matcher(r"\Aab\z");
+        assert!(matcher.find_match("ab").is_some());
+        assert!(matcher.find_match("xab").is_none());
+        assert!(matcher.find_match("abx").is_none());
+    }
+
+    #[test]
+    fn test_ignore_case() {
+        let matcher = RegexParser::new("grüße [a-c]+ \\w").with_ignore_case(true).parse().unwrap();
+        assert!(matcher.find_match("GRÜSSE").is_none());
+        assert_eq!(matcher.find_match("x GRÜßE aBc Q").unwrap().matched_text, "GRÜßE aBc Q");
+
+        let matcher = RegexParser::new("[^a-z]").with_ignore_case(true).parse().unwrap();
+        assert!(matcher.find_match("abcXYZ").is_none());
+    }
+
+    #[test]
+    fn test_ignore_case_chars_folding_into_others() {
+        let matcher = RegexParser::new("ß").with_ignore_case(true).parse().unwrap();
+        assert_eq!(matcher.find_match("STRAẞE").unwrap().matched_text, "ẞ");
+        let matcher = RegexParser::new("ᾀ").with_ignore_case(true).parse().unwrap();
+        assert!(matcher.find_match("ᾈ").is_some());
+
+        // Chars without case stay plain chars, so no other char may fold to them
+        let mut folded_from = std::collections::HashMap::new();
+        for c in (0..=char::MAX as u32).filter_map(char::from_u32) {
+            folded_from.entry(fold_case(c)).or_insert_with(Vec::new).push(c);
+        }
+        for chars in folded_from.values().filter(|chars| chars.len() > 1) {
+            assert!(chars.iter().all(|c| c.is_alphabetic()), "{chars:?}");
+        }
+    }
+
+    #[test]
+    fn test_inline_ignore_case_flag() {
+        let matcher = make_matcher("a(?i)bc");
+        assert!(matcher.find_match("aBC").is_some());
+        assert!(matcher.find_match("ABC").is_none());
+
+        let matcher = make_matcher("(?i:ab)c");
+        assert!(matcher.find_match("ABc").is_some());
+        assert!(matcher.find_match("ABC").is_none());
+
+        let matcher = RegexParser::new("(?-i:a)b").with_ignore_case(true).parse().unwrap();
+        assert!(matcher.find_match("aB").is_some());
+        assert!(matcher.find_match("AB").is_none());
+
+        let matcher = make_matcher("(?i)error|warning");
+        assert!(matcher.find_match("WARNING").is_some());
+
+        let matcher = make_matcher("(a(?i)b|c)d");
+        assert!(matcher.find_match("C").is_none());
+        assert!(matcher.find_match("Cd").is_some());
+        assert!(matcher.find_match("CD").is_none());
+        assert!(matcher.find_match("Ad").is_none());
+    }
+
+    #[test]
+    fn test_non_capturing_group() {
+        let matcher = make_matcher(r"(?:ab|cd)+(x)\1");
+        let m = matcher.find_match("abcdxx").unwrap();
+        assert_eq!(m.matched_text, "abcdxx");
+        assert_eq!(m.sub_matches.len(), 1);
+        assert_eq!(m.sub_matches[&1].matched_text, "x");
+    }
+
+    #[test]
+    fn test_ignore_case_backreference() {
+        let matcher = make_matcher(r"(?i)(\w+) ÄND \1");
+        assert!(matcher.find_match("Cat and cAT").is_none());
+        assert!(matcher.find_match("Cat änd cAT").is_some());
+        let matcher = make_matcher(r"(\w+) and \1");
+        assert!(matcher.find_match("Cat and cAT").is_none());
+    }
+
+    #[test]
+    fn test_flag_errors() {
+        assert_eq!(parse_err("a(?x)"), RegexError::BadFlag { flag: 'x', span: Span::new(3, 4) });
+        assert_eq!(parse_err("(?i"), RegexError::UnclosedParen { span: Span::new(0, 1) });
+    }
+
+    #[test]
+    fn test_byte_offsets_in_non_ascii_text() {
+        let text = "Grüße, Köln!";
+        let m = make_matcher("Köln").find_match(text).unwrap();
+        assert_eq!(m.offset, 9);
+        assert_eq!(m.span(), 9..14);
+        assert_eq!(&text[m.span()], "Köln");
+
+        let m = make_matcher(r"(ö)ln").find_match(text).unwrap();
+        assert_eq!(m.sub_matches[&1].span(), 10..12);
+
+        let matches = make_matcher("[äöü]").find_all_matches("äxöü");
+        let offsets: Vec<_> = matches.iter().map(|m| m.offset).collect();
+        assert_eq!(offsets, [0, 3, 5]);
+    }
+
+    #[test]
+    fn test_end_anchor_after_non_ascii() {
+        let matcher = make_matcher("ö$");
+        let m = matcher.find_match("Köln ö").unwrap();
+        assert_eq!(m.offset, 6);
+        assert!(matcher.find_match("öl").is_none());
+
+        let matcher = make_matcher(r"\w\b");
+        assert_eq!(matcher.find_match("äa b").unwrap().offset, 2);
+    }
+
+    #[test]
+    fn test_empty_matches_step_over_non_ascii() {
+        let matches = make_matcher("x*").find_all_matches("äö");
+        let offsets: Vec<_> = matches.iter().map(|m| m.offset).collect();
+        assert_eq!(offsets, [0, 2, 4]);
+    }
+
+    #[test]
+    fn test_ignore_case_backreference_with_different_lengths() {
+        // 'ſ' (2 bytes) folds to 's' (1 byte)
+        let matcher = make_matcher(r"(?i)(s+)-\1$");
+        let m = matcher.find_match("ss-ſS").unwrap();
+        assert_eq!(m.matched_text, "ss-ſS");
+    }
+
+    #[test]
+    fn test_long_line() {
+        let text = format!("{}\"user_id\":42{}", "{\"k\":1},".repeat(20_000), "x".repeat(20_000));
+        let m = make_matcher(r#""user_id":(\d+)"#).find_match(&text).unwrap();
+        assert_eq!(m.sub_matches[&1].matched_text, "42");
+        assert_eq!(make_matcher("^x").find_all_matches(&text).len(), 0);
+    }
+
+    #[test]
+    fn test_backtracker_repetition_on_long_line() {
+        let text = format!("aa{}", "b".repeat(300_000));
+        let matcher = make_matcher(r"(a)\1.*b$");
+        assert_eq!(matcher.find_match(&text).unwrap().matched_text.len(), text.len());
+        assert!(make_matcher(r"(a)\1.*z").find_match(&text).is_none());
+    }
+
+    #[test]
+    fn test_backtracker_group_repetition_on_long_line() {
+        let matcher = make_matcher(r"(a)\1(bc)*$");
+        let text = format!("aa{}", "bc".repeat(500));
+        assert_eq!(matcher.find_match(&text).unwrap().matched_text.len(), text.len());
+
+        // Too many nested repetitions give up instead of overflowing the stack
+        let text = format!("aa{}", "bc".repeat(200_000));
+        let result = matcher.find_at(&Input::new(text.as_bytes()), 0);
+        assert_eq!(result, Err(MatchError::TooDeep));
+    }
+}
@@ -0,0 +1,631 @@
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Write};
+use std::ops::Range;
+use thiserror::Error;
+use crate::{BinaryFiles, ColorMode, Config, MatchError, Regex};
+
+/// Display name used for stdin, as GNU grep does.
+pub const STDIN_NAME: &str = "(standard input)";
+
+/// A failed search, telling apart errors on the input, which only affect
+/// that input, from errors writing the results.
+#[derive(Debug, Error)]
+pub enum SearchError {
+    #[error("{0}")]
+    Read(io::Error),
+    /// It is unknown whether line `line` matches, so the search stopped there
+    #[error("line {line}: {error}")]
+    Match { line: usize, error: MatchError },
+    #[error("write error: {0}")]
+    Write(#[from] io::Error),
+}
+
+/// Searches line-oriented input from any reader and prints the results, so
+/// that stdin and files behave the same for every output option.
+pub struct Searcher<'a> {
+    config: &'a Config,
+    regex: &'a Regex,
+    show_filename: bool,
+    binary_files: BinaryFiles,
+    line_buffered: bool,
+    // Plain line selection only needs a yes/no answer per line
+    needs_spans: bool,
+    // Context is not shown with -o
+    before_context: usize,
+    after_context: usize,
+    // Whether context output was printed for any input so far, so that the
+    // first group of the next one is separated from it
+    printed_group: Cell<bool>,
+}
+
+impl<'a> Searcher<'a> {
+    pub fn new(config: &'a Config, regex: &'a Regex, show_filename: bool) -> Self {
+        Self {
+            config,
+            regex,
+            show_filename,
+            binary_files: config.binary_files_mode(),
+            line_buffered: config.line_buffered || atty::is(atty::Stream::Stdout),
+            needs_spans: config.only_matches || config.column || use_color(&config.color),
+            before_context: if config.only_matches { 0 } else { config.lines_before() },
+            after_context: if config.only_matches { 0 } else { config.lines_after() },
+            printed_group: Cell::new(false),
+        }
+    }
+
+    /// Searches `reader` line by line and writes the selected lines to `out`,
+    /// prefixed with `name` if file names are shown. Returns whether any line
+    /// was selected, or with `-L` whether the file was listed.
+    ///
+    /// Lines are matched and printed as bytes, so input that is not valid
+    /// UTF-8 can be searched and comes out unchanged.
+    ///
+    /// Input is binary once a NUL byte shows up, either in the first buffer
+    /// read or in a later line. Unless binary files are searched as text, no
+    /// more lines are printed from then on: the first match is reported as
+    /// "Binary file X matches" and ends the search.
+    ///
+    /// In count mode nothing but the number of selected lines (or matches)
+    /// is printed. When listing files, only `name` is printed if the file is
+    /// listed, and reading stops at the first selected line.
+    ///
+    /// For context, only the lines that may still be printed before the next
+    /// selected line are kept, never the whole input.
+    pub fn search(&self, mut reader: impl BufRead, name: &str, out: &mut impl Write) -> Result<bool, SearchError> {
+        let check_binary = self.binary_files != BinaryFiles::Text;
+        let mut binary = check_binary && reader.fill_buf().map_err(SearchError::Read)?.contains(&0);
+        let skip_binary = self.binary_files == BinaryFiles::WithoutMatch;
+        let listing = self.config.files_with_matches || self.config.files_without_match;
+        let counting = self.config.count || self.config.count_matches;
+        let mut buffer = vec![];
+        let mut found = false;
+        let mut count = 0;
+        let mut line_number = 0;
+        let mut offset = 0;
+        let mut context = Context::new(self.before_context, self.after_context);
+
+        while !(binary && skip_binary) {
+            buffer.clear();
+            let bytes_read = reader.read_until(b'\n', &mut buffer).map_err(SearchError::Read)?;
+            if bytes_read == 0 {
+                break; // EOF reached
+            }
+            let line = Line {
+                text: trim_line_ending(&buffer),
+                number: line_number + 1,
+                offset,
+            };
+            line_number += 1;
+            offset += bytes_read;
+            let match_failed = |error| SearchError::Match { line: line.number, error };
+            if check_binary && !binary && line.text.contains(&0) {
+                binary = true;
+                if skip_binary {
+                    break;
+                }
+            }
+            if listing {
+                if self.is_selected(line.text).map_err(match_failed)? {
+                    found = true;
+                    break;
+                }
+                continue;
+            }
+            if counting {
+                count += self.count_line(line.text).map_err(match_failed)?;
+                continue;
+            }
+            if binary {
+                if self.is_selected(line.text).map_err(match_failed)? {
+                    writeln!(out, "Binary file {name} matches")?;
+                    return Ok(true);
+                }
+                continue;
+            }
+            let printed = match self.select(line.text).map_err(match_failed)? {
+                Some(matches) => {
+                    found = true;
+                    self.print_before_context(&mut context, &line, name, out)?;
+                    self.print_selected(&line, &matches, name, out)?;
+                    context.selected(line.number);
+                    true
+                }
+                None if context.after_remaining > 0 => {
+                    self.write_prefix(name, &line, None, '-', out)?;
+                    write_line(line.text, out)?;
+                    context.printed_after(line.number);
+                    true
+                }
+                None => {
+                    context.remember(&line);
+                    false
+                }
+            };
+            if printed && self.line_buffered {
+                out.flush()?;
+            }
+        }
+
+        if listing {
+            let listed = found != self.config.files_without_match;
+            if listed {
+                writeln!(out, "{name}")?;
+            }
+            return Ok(listed);
+        }
+        if counting {
+            if self.show_filename {
+                write!(out, "{name}:")?;
+            }
+            writeln!(out, "{count}")?;
+            return Ok(count > 0);
+        }
+        Ok(found)
+    }
+
+    /// Number of selected lines in `text` (0 or 1), or with
+    /// `--count-matches` the number of matches in it. Lines selected by `-v`
+    /// contain no matches, so they count as one each.
+    fn count_line(&self, text: &[u8]) -> Result<usize, MatchError> {
+        if self.config.count_matches && !self.config.invert_match {
+            Ok(self.regex.find_all_bytes(text)?.len())
+        } else {
+            Ok(usize::from(self.is_selected(text)?))
+        }
+    }
+
+    /// Whether a line is selected, which with `-v` means it does not match.
+    fn is_selected(&self, text: &[u8]) -> Result<bool, MatchError> {
+        Ok(self.regex.is_match_bytes(text)? != self.config.invert_match)
+    }
+
+    /// The matches in `text` if the line is selected, `None` if not. Lines
+    /// are only searched for spans if they are needed for the output, so
+    /// the matches may be empty.
+    fn select(&self, text: &[u8]) -> Result<Option<Vec<Range<usize>>>, MatchError> {
+        if self.config.invert_match || !self.needs_spans {
+            return Ok(self.is_selected(text)?.then(Vec::new));
+        }
+        let matches = self.regex.find_all_bytes(text)?;
+        Ok((!matches.is_empty()).then_some(matches))
+    }
+
+    fn print_selected(&self, line: &Line, matches: &[Range<usize>], name: &str, out: &mut impl Write) -> io::Result<()> {
+        if self.config.only_matches {
+            // Lines selected by -v contain no matches, so nothing is printed
+            for m in non_empty(matches) {
+                self.write_prefix(name, line, Some(m.start), ':', out)?;
+                write_line(&line.text[m.clone()], out)?;
+            }
+            return Ok(());
+        }
+
+        let first_match = matches.first().map_or(0, |m| m.start);
+        self.write_prefix(name, line, Some(first_match), ':', out)?;
+        if matches.is_empty() {
+            write_line(line.text, out)
+        } else {
+            write_line(&colorize_line(line.text, matches, &self.config.color), out)
+        }
+    }
+
+    /// Prints the remembered lines before the selected `line`, preceded by
+    /// the group separator if they do not continue the previous output, which
+    /// may come from an earlier input.
+    fn print_before_context(&self, context: &mut Context, line: &Line, name: &str, out: &mut impl Write) -> io::Result<()> {
+        let first = context.buffered.front().map_or(line.number, |buffered| buffered.number);
+        let is_new_group = match context.last_printed {
+            Some(last) => first > last + 1,
+            None => self.printed_group.get(),
+        };
+        if context.is_enabled() && is_new_group && !self.config.no_group_separator {
+            writeln!(out, "{}", self.config.group_separator)?;
+        }
+        self.printed_group.set(true);
+
+        for buffered in context.buffered.drain(..) {
+            let line = Line {
+                text: &buffered.text,
+                number: buffered.number,
+                offset: buffered.offset,
+            };
+            self.write_prefix(name, &line, None, '-', out)?;
+            write_line(line.text, out)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the `file:line:column:offset:` prefix, with the parts that are
+    /// enabled. `match_start` is the start of the first match in the line, or
+    /// of the printed match with `-o`; context lines have none and no column.
+    /// The parts are followed by `separator`, `:` for selected lines and `-`
+    /// for context lines.
+    fn write_prefix(
+        &self,
+        name: &str,
+        line: &Line,
+        match_start: Option<usize>,
+        separator: char,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        if self.show_filename {
+            write!(out, "{name}{separator}")?;
+        }
+        if self.config.line_number {
+            write!(out, "{}{separator}", line.number)?;
+        }
+        if let Some(match_start) = match_start.filter(|_| self.config.column) {
+            write!(out, "{}{separator}", match_start + 1)?;
+        }
+        if self.config.byte_offset {
+            let start = match match_start {
+                Some(match_start) if self.config.only_matches => match_start,
+                _ => 0,
+            };
+            write!(out, "{}{separator}", line.offset + start)?;
+        }
+        Ok(())
+    }
+}
+
+/// Tracks which lines around the selected ones are printed as context.
+struct Context {
+    before: usize,
+    after: usize,
+    /// The last lines that were not printed, at most `before` of them
+    buffered: VecDeque<BufferedLine>,
+    /// Lines still to print after the last selected line
+    after_remaining: usize,
+    last_printed: Option<usize>,
+}
+
+struct BufferedLine {
+    text: Vec<u8>,
+    number: usize,
+    offset: usize,
+}
+
+impl Context {
+    fn new(before: usize, after: usize) -> Self {
+        Self {
+            before,
+            after,
+            buffered: VecDeque::with_capacity(before),
+            after_remaining: 0,
+            last_printed: None,
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.before > 0 || self.after > 0
+    }
+
+    fn selected(&mut self, number: usize) {
+        self.after_remaining = self.after;
+        self.last_printed = Some(number);
+    }
+
+    fn printed_after(&mut self, number: usize) {
+        self.after_remaining -= 1;
+        self.last_printed = Some(number);
+    }
+
+    /// Keeps a line that was not printed in case a selected line follows.
+    fn remember(&mut self, line: &Line) {
+        if self.before == 0 {
+            return;
+        }
+        // Reuse the allocation of the line that drops out
+        let mut text = if self.buffered.len() == self.before {
+            self.buffered.pop_front().map(|oldest| oldest.text).unwrap_or_default()
+        } else {
+            vec![]
+        };
+        text.clear();
+        text.extend_from_slice(line.text);
+        self.buffered.push_back(BufferedLine {
+            text,
+            number: line.number,
+            offset: line.offset,
+        });
+    }
+}
+
+/// A line of input without its line ending.
+struct Line<'b> {
+    text: &'b [u8],
+    /// 1-based line number
+    number: usize,
+    /// Byte offset of the line in the input
+    offset: usize,
+}
+
+fn write_line(line: &[u8], out: &mut impl Write) -> io::Result<()> {
+    out.write_all(line)?;
+    out.write_all(b"\n")
+}
+
+/// Strips `\n` or `\r\n` from the end of a line.
+fn trim_line_ending(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+fn use_color(color_mode: &ColorMode) -> bool {
+    match color_mode {
+        ColorMode::Always => true,
+        ColorMode::Auto => atty::is(atty::Stream::Stdout),
+        ColorMode::Never => false,
+    }
+}
+
+fn colorize_line(line: &[u8], matches: &[Range<usize>], color_mode: &ColorMode) -> Vec<u8> {
+    if use_color(color_mode) {
+        let mut colored_line = vec![];
+        let mut last_index = 0;
+
+        for m in non_empty(matches) {
+            colored_line.extend_from_slice(&line[last_index..m.start]);
+            colored_line.extend_from_slice(b"\x1b[1;31m"); // Start red color in bold
+            colored_line.extend_from_slice(&line[m.clone()]);
+            colored_line.extend_from_slice(b"\x1b[0m"); // Reset color
+            last_index = m.end;
+        }
+        colored_line.extend_from_slice(&line[last_index..]);
+        colored_line
+    } else {
+        line.to_vec()
+    }
+}
+
+/// Empty matches select a line but there is nothing to print for them.
+fn non_empty(matches: &[Range<usize>]) -> impl Iterator<Item = &Range<usize>> {
+    matches.iter().filter(|m| !m.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use std::cell::RefCell;
+
+    /// Searches `reader` like grep run with `args` and returns whether a
+    /// line was selected.
+    fn search_with(args: &[&str], reader: impl BufRead, name: &str, show_filename: bool, out: &mut impl Write) -> bool {
+        let config = Config::parse_from(["grep"].iter().chain(args));
+        let regex = Regex::new_with_ignore_case(&config.pattern, config.case_insensitive()).unwrap();
+        Searcher::new(&config, &regex, show_filename).search(reader, name, out).unwrap()
+    }
+
+    fn search(args: &[&str], input: &str, name: &str, show_filename: bool) -> String {
+        let mut out = vec![];
+        search_with(args, input.as_bytes(), name, show_filename, &mut out);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_colorize_non_ascii_line() {
+        let regex = Regex::new("ü+ß").unwrap();
+        let line = "Grüüße aus Köln";
+        let matches = regex.find_all_bytes(line.as_bytes()).unwrap();
+        assert_eq!(
+            colorize_line(line.as_bytes(), &matches, &ColorMode::Always),
+            "Gr\x1b[1;31müüß\x1b[0me aus Köln".as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_search_trims_line_endings() {
+        let input = "one\r\ntwo\nthree 3\r\n4";
+        assert_eq!(search(&["-E", r"\d|one"], input, "-", false), "one\nthree 3\n4\n");
+    }
+
+    #[test]
+    fn test_search_ignore_case() {
+        let input = "GRÜSSE\nGrüße\nfoo\n";
+        assert_eq!(search(&["-E", "-i", "grüße"], input, "-", false), "Grüße\n");
+        assert_eq!(search(&["-E", "-S", "FOO"], input, "-", false), "");
+        assert_eq!(search(&["-E", "-S", "grüsse"], input, "-", false), "GRÜSSE\n");
+    }
+
+    #[test]
+    fn test_search_invalid_utf8() {
+        let input = &b"a\xffbcd\nb\xffd\n"[..];
+        let search_bytes = |pattern: &str| {
+            let mut out = vec![];
+            search_with(&["-E", "-o", pattern], input, "-", false, &mut out);
+            out
+        };
+        // Invalid bytes pass through, but only byte mode `.` matches them
+        assert_eq!(search_bytes("b.d"), b"bcd\n");
+        assert_eq!(search_bytes("(?-u)b.d"), b"bcd\nb\xffd\n");
+        assert_eq!(search_bytes(r"(?-u)a\xff"), b"a\xff\n");
+    }
+
+    /// Hands out one line per read, like a pipe, and logs when it does.
+    struct LineReader<'l> {
+        lines: VecDeque<&'static [u8]>,
+        current: &'static [u8],
+        log: &'l RefCell<Vec<String>>,
+    }
+
+    impl io::Read for LineReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = self.fill_buf()?.len().min(buf.len());
+            buf[..len].copy_from_slice(&self.current[..len]);
+            self.consume(len);
+            Ok(len)
+        }
+    }
+
+    impl BufRead for LineReader<'_> {
+        fn fill_buf(&mut self) -> io::Result<&[u8]> {
+            if self.current.is_empty() {
+                if let Some(line) = self.lines.pop_front() {
+                    self.current = line;
+                    self.log.borrow_mut().push("read".into());
+                }
+            }
+            Ok(self.current)
+        }
+
+        fn consume(&mut self, amt: usize) {
+            self.current = &self.current[amt..];
+        }
+    }
+
+    /// Logs the output written before each flush.
+    struct FlushLog<'l> {
+        pending: Vec<u8>,
+        log: &'l RefCell<Vec<String>>,
+    }
+
+    impl Write for FlushLog<'_> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.pending.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            let written = String::from_utf8(std::mem::take(&mut self.pending)).unwrap();
+            self.log.borrow_mut().push(format!("flush {written:?}"));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_line_buffered() {
+        let log = RefCell::new(vec![]);
+        let reader = LineReader {
+            lines: [&b"match 1\n"[..], b"no\n", b"match 2\n"].into(),
+            current: b"",
+            log: &log,
+        };
+        let mut out = FlushLog { pending: vec![], log: &log };
+        assert!(search_with(&["-E", "--line-buffered", "match"], reader, "-", false, &mut out));
+        // Each selected line is flushed before the next line is read
+        assert_eq!(log.into_inner(), ["read", r#"flush "match 1\n""#, "read", "read", r#"flush "match 2\n""#]);
+    }
+
+    #[test]
+    fn test_binary_files() {
+        let input = "text match\nbin\0ary match\nmore match\n";
+        assert_eq!(search(&["-E", "match"], input, "f", false), "Binary file f matches\n");
+        assert_eq!(search(&["-E", "-o", "more"], input, "f", true), "Binary file f matches\n");
+        assert_eq!(search(&["-E", "-I", "match"], input, "f", false), "");
+        assert_eq!(search(&["-E", "--binary-files=without-match", "nothing"], input, "f", false), "");
+        assert_eq!(search(&["-E", "-a", "ary"], input, "f", false), "bin\0ary match\n");
+        assert_eq!(search(&["-E", "--binary-files=text", "ary"], input, "f", false), "bin\0ary match\n");
+    }
+
+    #[test]
+    fn test_late_nul_byte() {
+        // A NUL after the first buffer only affects the lines after it
+        let input = format!("{}\nmatch\n\0\nmatch\n", "x".repeat(10_000));
+        let search_buffered = |args: &[&str]| {
+            let reader = io::BufReader::with_capacity(4096, input.as_bytes());
+            let mut out = vec![];
+            search_with(args, reader, "f", false, &mut out);
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(search_buffered(&["-E", "match"]), "match\nBinary file f matches\n");
+        assert_eq!(search_buffered(&["-E", "-I", "match"]), "match\n");
+    }
+
+    #[test]
+    fn test_line_positions() {
+        let input = "alpha\nbeta gamma\r\ndelta mama\n";
+        assert_eq!(search(&["-E", "-n", "ma"], input, "f", true), "f:2:beta gamma\nf:3:delta mama\n");
+        assert_eq!(search(&["-E", "-b", "ma"], input, "-", false), "6:beta gamma\n18:delta mama\n");
+        assert_eq!(search(&["-E", "-n", "--column", "ma"], input, "-", false), "2:9:beta gamma\n3:7:delta mama\n");
+        assert_eq!(
+            search(&["-E", "-o", "-n", "-b", "--column", "ma"], input, "-", false),
+            "2:9:14:ma\n3:7:24:ma\n3:9:26:ma\n"
+        );
+    }
+
+    #[test]
+    fn test_word_boundaries() {
+        let input = " 42 apples\n(id) x\n";
+        assert_eq!(search(&["-E", r"\b\d+\b"], input, "-", false), " 42 apples\n");
+        assert_eq!(search(&["-E", "-o", r"\<\w+\>"], input, "-", false), "42\napples\nid\nx\n");
+        assert_eq!(search(&["-E", r"\<über"], "über alles\n", "-", false), "über alles\n");
+        assert_eq!(search(&["-E", "-o", r"\w+"], "Grüße!\n", "-", false), "Grüße\n");
+    }
+
+    #[test]
+    fn test_invert_match() {
+        let input = "foo\nbar\nbaz foo\nqux\n";
+        assert_eq!(search(&["-E", "-v", "foo"], input, "-", false), "bar\nqux\n");
+        assert_eq!(search(&["-E", "-v", "-n", "--color=always", "ba"], input, "-", false), "1:foo\n4:qux\n");
+        assert_eq!(search(&["-E", "-v", "-o", "foo"], input, "-", false), "");
+        assert_eq!(search(&["-E", "-v", "."], input, "-", false), "");
+        assert_eq!(search(&["-E", "-v", "x"], "a\0\n", "f", false), "Binary file f matches\n");
+    }
+
+    #[test]
+    fn test_count() {
+        let input = "foo foo\nbar\nfoo\n";
+        assert_eq!(search(&["-E", "-c", "foo"], input, "f", false), "2\n");
+        assert_eq!(search(&["-E", "-c", "foo"], input, "f", true), "f:2\n");
+        assert_eq!(search(&["-E", "-c", "-v", "foo"], input, "f", false), "1\n");
+        assert_eq!(search(&["-E", "-c", "x"], input, "f", false), "0\n");
+        assert_eq!(search(&["-E", "--count-matches", "foo"], input, "f", true), "f:3\n");
+        assert_eq!(search(&["-E", "--count-matches", "$"], input, "f", false), "3\n");
+        assert_eq!(search(&["-E", "-c", "a"], "a\0\na\n", "f", false), "2\n");
+        assert_eq!(search(&["-E", "-c", "-I", "a"], "a\0\na\n", "f", false), "0\n");
+    }
+
+    #[test]
+    fn test_list_files() {
+        assert_eq!(search(&["-E", "-l", "o"], "foo\nbar\n", "f", false), "f\n");
+        assert_eq!(search(&["-E", "-l", "x"], "foo\nbar\n", "f", true), "");
+        assert_eq!(search(&["-E", "-L", "x"], "foo\nbar\n", "f", true), "f\n");
+        assert_eq!(search(&["-E", "-L", "o"], "foo\nbar\n", "f", true), "");
+        assert_eq!(search(&["-E", "-l", "-c", "-v", "o"], "foo\nbar\n", "f", true), "f\n");
+        assert_eq!(search(&["-E", "-l", "a"], "a\0\n", "f", true), "f\n");
+    }
+
+    #[test]
+    fn test_list_files_stops_at_first_match() {
+        let mut reader = io::Cursor::new("no\nmatch\nrest\n");
+        assert!(search_with(&["-E", "-l", "match"], &mut reader, "f", false, &mut vec![]));
+        assert_eq!(reader.position(), 9);
+    }
+
+    #[test]
+    fn test_context() {
+        let input = "a\nb\nMATCH 1\nc\nd\ne\nf\nMATCH 2\nMATCH 3\ng\n";
+        assert_eq!(
+            search(&["-E", "-C", "1", "MATCH"], input, "-", false),
+            "b\nMATCH 1\nc\n--\nf\nMATCH 2\nMATCH 3\ng\n"
+        );
+        assert_eq!(
+            search(&["-E", "-n", "-B", "2", "MATCH"], input, "f", true),
+            "f-1-a\nf-2-b\nf:3:MATCH 1\n--\nf-6-e\nf-7-f\nf:8:MATCH 2\nf:9:MATCH 3\n"
+        );
+        assert_eq!(
+            search(&["-E", "-A", "3", "--group-separator=##", "MATCH"], input, "-", false),
+            "MATCH 1\nc\nd\ne\n##\nMATCH 2\nMATCH 3\ng\n"
+        );
+        assert_eq!(
+            search(&["-E", "-A", "1", "--no-group-separator", "MATCH"], input, "-", false),
+            "MATCH 1\nc\nMATCH 2\nMATCH 3\ng\n"
+        );
+        // Adjacent groups are merged, and -C is overridden by -A/-B
+        assert_eq!(
+            search(&["-E", "-C", "5", "-A", "0", "-B", "1", "[13]"], input, "-", false),
+            "b\nMATCH 1\n--\nMATCH 2\nMATCH 3\n"
+        );
+        assert_eq!(search(&["-E", "-v", "-C", "1", "[a-g]"], input, "-", false), "b\nMATCH 1\nc\n--\nf\nMATCH 2\nMATCH 3\ng\n");
+        assert_eq!(search(&["-E", "-o", "-C", "1", "[0-9]"], input, "-", false), "1\n2\n3\n");
+    }
+
+    #[test]
+    fn test_search_prefixes_every_output_line() {
+        let input = "a1b2\nxyz\n";
+        assert_eq!(search(&["-E", r"\d"], input, "f.txt", true), "f.txt:a1b2\n");
+        assert_eq!(search(&["-E", "-o", r"\d"], input, STDIN_NAME, true), "(standard input):1\n(standard input):2\n");
+    }
+}